@@ -63,4 +63,16 @@ impl Example for S {
             },
         ))))
     }
+
+    async fn bi_di_stream(
+        &self,
+        req: Request<RecvStream<Input>>,
+    ) -> Result<Response<BoxStream<'static, Result<Output, Status>>>, Status> {
+        Ok(Response::new(Box::pin(req.into_inner().map(|input| {
+            input.map(|input| Output {
+                id: input.id,
+                desc: input.desc,
+            })
+        }))))
+    }
 }
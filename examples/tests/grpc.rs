@@ -1,18 +1,19 @@
-use std::{future::Future, net::SocketAddr};
-
 use examples::{
     volo_gen::proto_gen::example::{
         ExampleClient, ExampleClientBuilder, ExampleServer, Input, Output,
     },
     S,
 };
-use grpc_web::{Cors, WebLayer};
-use tokio::{join, time::Duration, try_join};
+use grpc_web::{
+    test_utils::{TestServer, TestServerHandle},
+    Cors, WebLayer,
+};
+use tokio::{join, try_join};
 use tokio_stream::{self as stream, Stream, StreamExt};
 use volo::net::Address;
 use volo_grpc::{
     server::{Server, ServiceBuilder},
-    BoxError, Response, Status,
+    Response, Status,
 };
 
 #[tokio::test]
@@ -70,6 +71,31 @@ async fn smoke_server_stream() {
 
     assert!(r1 == r2 && r2 == r3 && r3 == r4);
 }
+
+#[tokio::test]
+async fn smoke_bidi_stream() {
+    let (c1, c2, c3, c4) = spawn().await.expect("clients");
+
+    let input_stream = || stream::iter(vec![input(), input()]);
+
+    let (r1, r2, r3, r4) = try_join!(
+        c1.bi_di_stream(input_stream()),
+        c2.bi_di_stream(input_stream()),
+        c3.bi_di_stream(input_stream()),
+        c4.bi_di_stream(input_stream()),
+    )
+    .expect("responses");
+
+    assert!(meta(&r1) == meta(&r2) && meta(&r2) == meta(&r3) && meta(&r3) == meta(&r4));
+
+    let r1 = stream(r1).await;
+    let r2 = stream(r2).await;
+    let r3 = stream(r3).await;
+    let r4 = stream(r4).await;
+
+    assert!(r1 == r2 && r2 == r3 && r3 == r4);
+}
+
 #[tokio::test]
 async fn smoke_error() {
     let (c1, c2, c3, c4) = spawn().await.expect("clients");
@@ -94,53 +120,48 @@ async fn smoke_error() {
     assert!(status(&s1) == status(&s2) && status(&s2) == status(&s3) && status(&s3) == status(&s4))
 }
 
-async fn grpc(accept_h1: bool) -> (impl Future<Output = Result<(), BoxError>>, SocketAddr) {
-    let addr: SocketAddr = "[::]:8081".parse().unwrap();
-    let address = Address::from(addr);
-
-    let fut = Server::new()
-        .accept_http1(accept_h1)
-        .add_service(ServiceBuilder::new(ExampleServer::new(S)).build())
-        .run(address);
-
-    (fut, addr)
+async fn grpc(accept_h1: bool) -> TestServerHandle {
+    TestServer::builder()
+        .spawn(|address| {
+            Server::new()
+                .accept_http1(accept_h1)
+                .add_service(ServiceBuilder::new(ExampleServer::new(S)).build())
+                .run(address)
+        })
+        .await
 }
 
-async fn grpc_web(accept_h1: bool) -> (impl Future<Output = Result<(), BoxError>>, SocketAddr) {
-    let addr: SocketAddr = "[::]:8081".parse().unwrap();
-    let address = Address::from(addr);
-
-    let config = grpc_web::Config::default().allow_origins(vec!["http://foo.com"]);
-
-    let fut = Server::new()
-        .accept_http1(accept_h1)
-        .layer_outer(WebLayer::new(Cors::new(config)))
-        .add_service(ServiceBuilder::new(ExampleServer::new(S)).build())
-        .run(address);
-
-    (fut, addr)
+async fn grpc_web(accept_h1: bool) -> TestServerHandle {
+    let config = grpc_web::Config::default()
+        .allow_origins(vec!["http://foo.com"])
+        .unwrap();
+
+    TestServer::builder()
+        .spawn(|address| {
+            Server::new()
+                .accept_http1(accept_h1)
+                .layer_outer(WebLayer::new(Cors::new(config)))
+                .add_service(ServiceBuilder::new(ExampleServer::new(S)).build())
+                .run(address)
+        })
+        .await
 }
 
 async fn spawn() -> Result<(ExampleClient, ExampleClient, ExampleClient, ExampleClient), Status> {
-    let ((s1, u1), (s2, u2), (s3, u3), (s4, u4)) =
-        join!(grpc(true), grpc(false), grpc_web(true), grpc_web(false));
-
-    tokio::spawn(async move { join!(s1, s2, s3, s4) });
-
-    tokio::time::sleep(Duration::from_millis(30)).await;
+    let (s1, s2, s3, s4) = join!(grpc(true), grpc(false), grpc_web(true), grpc_web(false));
 
     Ok((
         ExampleClientBuilder::new("example1")
-            .address(Address::from(u1))
+            .address(Address::from(s1.address()))
             .build(),
         ExampleClientBuilder::new("example2")
-            .address(Address::from(u2))
+            .address(Address::from(s2.address()))
             .build(),
         ExampleClientBuilder::new("example3")
-            .address(Address::from(u3))
+            .address(Address::from(s3.address()))
             .build(),
         ExampleClientBuilder::new("example4")
-            .address(Address::from(u4))
+            .address(Address::from(s4.address()))
             .build(),
     ))
 }
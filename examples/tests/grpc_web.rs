@@ -1,12 +1,10 @@
-use std::{net::SocketAddr, time::Duration};
-
 use base64::{engine::general_purpose, Engine};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
 use examples::{
     volo_gen::proto_gen::example::{ExampleServer, Input, Output},
     S,
 };
-use grpc_web::{Cors, WebLayer};
+use grpc_web::{test_utils::TestServer, Cors, WebLayer};
 use hyper::{
     http::{header, StatusCode},
     Body, Client, Method, Request, Uri,
@@ -25,7 +23,7 @@ async fn binary_request() {
     let content_type = content_type.to_str().unwrap();
 
     assert_eq!(res.status(), StatusCode::OK);
-    assert_eq!(content_type, "application/grpc-web+proto");
+    assert_eq!(content_type, "application/grpc-web");
 
     let (message, trailers) = decode_body(res.into_body(), content_type).await;
     let expected = Output {
@@ -48,7 +46,7 @@ async fn text_request() {
     let content_type = content_type.to_str().unwrap();
 
     assert_eq!(res.status(), StatusCode::OK);
-    assert_eq!(content_type, "application/grpc-web-text+proto");
+    assert_eq!(content_type, "application/grpc-web-text");
 
     let (message, trailers) = decode_body(res.into_body(), content_type).await;
     let expected = Output {
@@ -60,6 +58,51 @@ async fn text_request() {
     assert_eq!(&trailers[..], b"grpc-status:0\r\n");
 }
 
+#[tokio::test]
+async fn bidi_stream_request() {
+    use header::{ACCEPT, CONTENT_TYPE, ORIGIN};
+
+    let config = grpc_web::Config::default()
+        .allow_origins(vec!["http://example.com"])
+        .unwrap();
+    let server_url = spawn_with(config).await;
+    let client = Client::new();
+
+    let mut body = BytesMut::new();
+    body.extend_from_slice(&encode_body());
+    body.extend_from_slice(&encode_body());
+
+    let req = Request::builder()
+        .method(Method::POST)
+        .header(CONTENT_TYPE, "application/grpc-web")
+        .header(ORIGIN, "http://example.com")
+        .header(ACCEPT, "application/grpc-web")
+        .uri(format!("{}/example.Example/BiDiStream", server_url))
+        .body(Body::from(body.freeze()))
+        .unwrap();
+    let res = client.request(req).await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let mut body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    let mut messages = Vec::new();
+
+    while body[0] & 0x80 == 0 {
+        body.advance(1);
+        let len = body.get_u32();
+        messages.push(Output::decode(&mut body.split_to(len as usize)).expect("decode"));
+    }
+    body.advance(5);
+
+    let expected = Output {
+        id: 1,
+        desc: "one".into(),
+    };
+
+    assert_eq!(messages, vec![expected.clone(), expected]);
+    assert_eq!(&body[..], b"grpc-status:0\r\n");
+}
+
 #[tokio::test]
 async fn origin_not_allowed() {
     let server_url = spawn("http://foo.com").await;
@@ -69,25 +112,231 @@ async fn origin_not_allowed() {
     let res = client.request(req).await.unwrap();
 
     assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(res.headers()[header::CACHE_CONTROL], "no-store");
+    assert!(res
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_EXPOSE_HEADERS));
 }
 
-async fn spawn(allowed_origin: &str) -> String {
-    let addr: SocketAddr = "[::]:8080".parse().unwrap();
-    let address = volo::net::Address::from(addr);
-    let config = grpc_web::Config::default().allow_origins(vec![allowed_origin]);
-
-    tokio::spawn(async move {
-        Server::new()
-            .accept_http1(true)
-            .layer_outer(WebLayer::new(Cors::new(config)))
-            .add_service(ServiceBuilder::new(ExampleServer::new(S)).build())
-            .run(address)
-            .await
-            .unwrap()
+#[tokio::test]
+async fn preflight_forbidden_request_header() {
+    let config = grpc_web::Config::default()
+        .allow_origins(vec!["http://example.com"])
+        .unwrap();
+    let server_url = spawn_with(config).await;
+    let client = Client::new();
+
+    let req = build_preflight(server_url, "POST", "host,x-grpc-web");
+    let res = client.request(req).await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn preflight_success_status() {
+    let config = grpc_web::Config::default()
+        .allow_origins(vec!["http://example.com"])
+        .unwrap()
+        .preflight_success_status(StatusCode::OK);
+    let server_url = spawn_with(config).await;
+    let client = Client::new();
+
+    let req = build_preflight(server_url, "POST", "x-grpc-web");
+    let res = client.request(req).await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn unrecognized_method() {
+    let config = grpc_web::Config::default()
+        .allow_origins(vec!["http://example.com"])
+        .unwrap()
+        .on_unrecognized_method(|method| {
+            if method == Method::PUT {
+                StatusCode::NOT_IMPLEMENTED
+            } else {
+                StatusCode::METHOD_NOT_ALLOWED
+            }
+        });
+    let server_url = spawn_with(config).await;
+    let client = Client::new();
+
+    let mut req = build_request(server_url, "grpc-web", "grpc-web");
+    *req.method_mut() = Method::PUT;
+    let res = client.request(req).await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::NOT_IMPLEMENTED);
+}
+
+#[tokio::test]
+async fn preflight_debug_cors_errors() {
+    let config = grpc_web::Config::default()
+        .allow_origins(vec!["http://foo.com"])
+        .unwrap()
+        .debug_cors_errors(true);
+    let server_url = spawn_with(config).await;
+    let client = Client::new();
+
+    let req = build_preflight(server_url, "POST", "x-grpc-web");
+    let res = client.request(req).await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    assert_eq!(
+        res.headers()[header::ACCESS_CONTROL_ALLOW_METHODS],
+        "POST,OPTIONS"
+    );
+    assert!(!res
+        .headers()
+        .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+}
+
+#[tokio::test]
+async fn health_check() {
+    let config = grpc_web::Config::default()
+        .allow_origins(vec!["http://example.com"])
+        .unwrap()
+        .health_check_path("/health");
+    let server_url = spawn_with(config).await;
+    let client = Client::new();
+
+    let uri = format!("{}/health", server_url).parse::<Uri>().unwrap();
+    let res = client.get(uri).await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()[header::CONTENT_TYPE], "application/json");
+
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    assert_eq!(&body[..], br#"{"status":"serving"}"#);
+}
+
+#[tokio::test]
+async fn trace_not_implemented() {
+    let config = grpc_web::Config::default()
+        .allow_origins(vec!["http://example.com"])
+        .unwrap();
+    let server_url = spawn_with(config).await;
+    let client = Client::new();
+
+    let mut req = build_request(server_url, "grpc-web", "grpc-web");
+    *req.method_mut() = Method::TRACE;
+    let res = client.request(req).await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::NOT_IMPLEMENTED);
+}
+
+#[tokio::test]
+async fn unsupported_subformat() {
+    let config = grpc_web::Config::default()
+        .allow_origins(vec!["http://example.com"])
+        .unwrap();
+    let server_url = spawn_with(config).await;
+    let client = Client::new();
+
+    let mut req = build_request(server_url, "grpc-web", "grpc-web");
+    req.headers_mut().insert(
+        header::CONTENT_TYPE,
+        header::HeaderValue::from_static("application/grpc-web+thrift"),
+    );
+    let res = client.request(req).await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    let body = std::str::from_utf8(&body).unwrap();
+    assert!(body.contains("application/grpc-web+proto"));
+}
+
+#[tokio::test]
+async fn response_hook() {
+    let config = grpc_web::Config::default()
+        .allow_origins(vec!["http://example.com"])
+        .unwrap();
+    let layer = WebLayer::new(Cors::new(config)).with_response_hook(|mut res| {
+        res.headers_mut()
+            .insert("x-signature", header::HeaderValue::from_static("signed"));
+        res
     });
-    tokio::time::sleep(Duration::from_millis(30)).await;
+    let server_url = spawn_layer(layer).await;
+    let client = Client::new();
 
-    format!("http://{}", addr)
+    let req = build_request(server_url, "grpc-web", "grpc-web");
+    let res = client.request(req).await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["x-signature"], "signed");
+}
+
+#[tokio::test]
+async fn dry_run() {
+    let config = grpc_web::Config::default()
+        .allow_origins(vec!["http://example.com"])
+        .unwrap();
+    let layer = WebLayer::new(Cors::new(config)).dry_run(true);
+    let server_url = spawn_layer(layer).await;
+    let client = Client::new();
+
+    let req = build_request(server_url, "grpc-web", "grpc-web");
+    let res = client.request(req).await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+
+    let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+    assert_eq!(body[0], 0x80);
+    assert!(body.ends_with(b"grpc-status:0\r\n"));
+}
+
+#[tokio::test]
+async fn multi_cors() {
+    let foo = grpc_web::Config::default()
+        .allow_origins(vec!["http://foo.com"])
+        .unwrap();
+    let example = grpc_web::Config::default()
+        .allow_origins(vec!["http://example.com"])
+        .unwrap()
+        .expose_headers(vec!["x-brand"]);
+    let fallback = grpc_web::Config::default().allow_origins_unchecked(Vec::<&str>::new());
+    let layer = WebLayer::multi_cors(
+        vec![Cors::new(foo), Cors::new(example)],
+        Cors::new(fallback),
+    );
+    let server_url = spawn_layer(layer).await;
+    let client = Client::new();
+
+    let req = build_request(server_url, "grpc-web", "grpc-web");
+    let res = client.request(req).await.unwrap();
+
+    assert_eq!(res.status(), StatusCode::OK);
+    let exposed = res.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS]
+        .to_str()
+        .unwrap();
+    assert!(exposed.contains("x-brand"));
+}
+
+async fn spawn(allowed_origin: &str) -> String {
+    let config = grpc_web::Config::default()
+        .allow_origins(vec![allowed_origin])
+        .unwrap();
+
+    spawn_with(config).await
+}
+
+async fn spawn_with(config: grpc_web::Config) -> String {
+    spawn_layer(WebLayer::new(Cors::new(config))).await
+}
+
+async fn spawn_layer(layer: WebLayer) -> String {
+    let server = TestServer::builder()
+        .spawn(|address| {
+            Server::new()
+                .accept_http1(true)
+                .layer_outer(layer)
+                .add_service(ServiceBuilder::new(ExampleServer::new(S)).build())
+                .run(address)
+        })
+        .await;
+
+    server.url()
 }
 
 fn encode_body() -> Bytes {
@@ -137,10 +386,27 @@ fn build_request(base_uri: String, content_type: &str, accept: &str) -> Request<
         .unwrap()
 }
 
+fn build_preflight(base_uri: String, method: &str, request_headers: &str) -> Request<Body> {
+    use header::{ACCESS_CONTROL_REQUEST_HEADERS, ACCESS_CONTROL_REQUEST_METHOD, ORIGIN};
+
+    let request_uri = format!("{}/{}/{}", base_uri, "example.Example", "UnaryCall")
+        .parse::<Uri>()
+        .unwrap();
+
+    Request::builder()
+        .method(Method::OPTIONS)
+        .header(ORIGIN, "http://example.com")
+        .header(ACCESS_CONTROL_REQUEST_METHOD, method)
+        .header(ACCESS_CONTROL_REQUEST_HEADERS, request_headers)
+        .uri(request_uri)
+        .body(Body::empty())
+        .unwrap()
+}
+
 async fn decode_body(body: Body, content_type: &str) -> (Output, Bytes) {
     let mut body = hyper::body::to_bytes(body).await.unwrap();
 
-    if content_type == "application/grpc-web-text+proto" {
+    if content_type.starts_with("application/grpc-web-text") {
         body = general_purpose::STANDARD.decode(body).unwrap().into()
     }
 
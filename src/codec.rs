@@ -1,72 +1,247 @@
 use std::{
     error::Error,
+    marker::PhantomData,
     pin::Pin,
     task::{Context, Poll},
 };
 
 use base64::{engine::general_purpose, Engine};
 use bytes::{Buf, BufMut, Bytes, BytesMut};
-use futures_core::{ready, Stream};
+use futures_core::{ready, stream::FusedStream, Stream};
 use http::{header, HeaderMap, HeaderValue};
-use http_body::{Body, SizeHint};
+use http_body::{Body, Full, SizeHint};
 use pin_project::pin_project;
 use volo_grpc::Status;
 
-use crate::{GRPC_WEB_PROTO, GRPC_WEB_TEXT, GRPC_WEB_TEXT_PROTO};
+#[cfg(feature = "compression")]
+use crate::compression::{decompress_frame, Compression};
+use crate::{
+    direction::{self, Request, Response},
+    proto::{self, FrameHeader, TrailerFrame, COMPRESSED_FLAG, FRAME_HEADER_SIZE},
+    GRPC_WEB, GRPC_WEB_PROTO, GRPC_WEB_TEXT, GRPC_WEB_TEXT_JSON, GRPC_WEB_TEXT_PROTO,
+};
 
 const BUFFER_SIZE: usize = 8 * 1024;
 
-const FRAME_HEADER_SIZE: usize = 5;
-
-const GRPC_WEB_TRAILERS_BIT: u8 = 0b1000_0000;
-
-#[derive(Copy, Clone, PartialEq, Debug)]
-enum Direction {
-    Request,
-    Response,
-}
-
+/// The transport encoding of a grpc-web body.
+///
+/// More encodings may be added, such as multipart responses should the grpc-web protocol adopt
+/// them for server push.
 #[derive(Copy, Clone, PartialEq, Debug)]
-pub(crate) enum Encoding {
+#[non_exhaustive]
+pub enum Encoding {
+    /// `application/grpc-web-text`, frames are base64 encoded.
     Base64,
+    /// `application/grpc-web`, frames are sent as is.
     None,
 }
 
+/// A body translating between grpc-web and gRPC framing.
+///
+/// Errors are reported as [`Status`] by default, see [`WebCall::with_error_type`] to convert them
+/// to another type `E`.
+///
+/// The [`direction`](crate::direction) `D` is part of the type, so that a response body cannot
+/// be mistaken for a request one.
+///
+/// `WebCall<B, D, E>` is [`Unpin`] whenever `B` is.
 #[pin_project]
-pub(crate) struct WebCall<B> {
+pub struct WebCall<B, D, E = Status> {
     #[pin]
     inner: B,
     buf: BytesMut,
-    direction: Direction,
+    decoded: BytesMut,
+    // Request data already decoded by `peek_first_frame`, yielded before anything else.
+    peeked: BytesMut,
+    direction: PhantomData<fn() -> D>,
     encoding: Encoding,
     poll_trailers: bool,
+    bytes_decoded: u64,
+    bytes_encoded: u64,
+    chunks_processed: u32,
+    terminated: bool,
+    raw_pass_through: bool,
+    // Bytes still expected for the current frame of a binary request.
+    frame_remaining: usize,
+    #[cfg(feature = "compression")]
+    compression: Option<Compression>,
+    trailer_observer: Option<Box<dyn Fn(&HeaderMap) + Send + Sync>>,
+    // Response trailers sent when the inner body has none.
+    default_trailers: Option<HeaderMap>,
+    error: PhantomData<fn() -> E>,
 }
 
-impl<B> WebCall<B> {
-    pub(crate) fn request(inner: B, encoding: Encoding) -> Self {
-        Self::new(inner, Direction::Request, encoding)
+impl<B> WebCall<B, Request> {
+    /// Wraps a grpc-web request body, decoding it into a gRPC body.
+    pub fn request(inner: B, encoding: Encoding) -> Self {
+        Self::new(inner, encoding)
     }
+}
 
-    pub(crate) fn response(inner: B, encoding: Encoding) -> Self {
-        Self::new(inner, Direction::Response, encoding)
+impl<B> WebCall<B, Response> {
+    /// Wraps a gRPC response body, encoding it and its trailers as a grpc-web body.
+    pub fn response(inner: B, encoding: Encoding) -> Self {
+        Self::new(inner, encoding)
     }
+}
 
-    fn new(inner: B, direction: Direction, encoding: Encoding) -> Self {
+impl<B, D: direction::Direction, E> WebCall<B, D, E> {
+    fn new(inner: B, encoding: Encoding) -> Self {
         WebCall {
             inner,
-            buf: BytesMut::with_capacity(match (direction, encoding) {
-                (Direction::Response, Encoding::Base64) => BUFFER_SIZE,
-                _ => 0,
-            }),
-            direction,
+            buf: match (D::IS_REQUEST, encoding) {
+                (false, Encoding::Base64) => BytesMut::with_capacity(BUFFER_SIZE),
+                _ => BytesMut::new(),
+            },
+            decoded: BytesMut::new(),
+            peeked: BytesMut::new(),
+            direction: PhantomData,
             encoding,
             poll_trailers: true,
+            bytes_decoded: 0,
+            bytes_encoded: 0,
+            chunks_processed: 0,
+            terminated: false,
+            raw_pass_through: false,
+            frame_remaining: 0,
+            #[cfg(feature = "compression")]
+            compression: None,
+            trailer_observer: None,
+            default_trailers: None,
+            error: PhantomData,
         }
     }
+}
+
+impl<B, D, E> WebCall<B, D, E> {
+    /// Reports errors as `E2`, converted from the [`Status`] the codec produces.
+    pub fn with_error_type<E2>(self) -> WebCall<B, D, E2>
+    where
+        E2: From<Status>,
+    {
+        WebCall {
+            inner: self.inner,
+            buf: self.buf,
+            decoded: self.decoded,
+            peeked: self.peeked,
+            direction: self.direction,
+            encoding: self.encoding,
+            poll_trailers: self.poll_trailers,
+            bytes_decoded: self.bytes_decoded,
+            bytes_encoded: self.bytes_encoded,
+            chunks_processed: self.chunks_processed,
+            terminated: self.terminated,
+            raw_pass_through: self.raw_pass_through,
+            frame_remaining: self.frame_remaining,
+            #[cfg(feature = "compression")]
+            compression: self.compression,
+            trailer_observer: self.trailer_observer,
+            default_trailers: self.default_trailers,
+            error: PhantomData,
+        }
+    }
+
+    /// Calls `f` with the response trailers right before they are encoded.
+    #[must_use]
+    pub fn with_trailer_observer<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&HeaderMap) + Send + Sync + 'static,
+    {
+        self.trailer_observer = Some(Box::new(f));
+        self
+    }
+
+    /// Forwards response data unchanged, for bodies that are already grpc-web encoded. Only the
+    /// trailers are still framed and encoded.
+    #[must_use]
+    pub fn with_raw_pass_through(mut self, raw_pass_through: bool) -> Self {
+        self.raw_pass_through = raw_pass_through;
+        self
+    }
+
+    /// Decompresses response messages compressed with `compression` before encoding them.
+    #[cfg(feature = "compression")]
+    pub(crate) fn with_compression(mut self, compression: Option<Compression>) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Returns the number of bytes yielded by a request body after decoding.
+    pub fn bytes_decoded(&self) -> u64 {
+        self.bytes_decoded
+    }
+
+    /// Returns the number of bytes yielded by a response body after encoding, trailers included.
+    pub fn bytes_encoded(&self) -> u64 {
+        self.bytes_encoded
+    }
+
+    /// Returns the number of chunks yielded so far, in either direction. A chunk may hold several
+    /// frames, or only part of one.
+    pub fn chunks_processed(&self) -> u32 {
+        self.chunks_processed
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Consumes the `WebCall`, returning the wrapped body.
+    pub fn into_inner(self) -> B {
+        self.inner
+    }
+}
+
+impl WebCall<Full<Bytes>, Response> {
+    /// Wraps a single, already framed, unary response message, followed by `grpc-status: 0`
+    /// trailers.
+    pub fn unary_response(data: Bytes, encoding: Encoding) -> Self {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+
+        let mut call = Self::response(Full::new(data), encoding);
+        call.default_trailers = Some(trailers);
+        call
+    }
+}
+
+impl<B, E> WebCall<B, Request, E>
+where
+    B: Body<Data = Bytes>,
+    B::Error: Error,
+{
+    /// Reads the header of the first frame of the request, without consuming it: the frame is
+    /// still yielded whole by the body. Must be called before polling any data.
+    ///
+    /// Returns `None` if the request ends before a whole header was read.
+    pub fn peek_first_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<FrameHeader, Status>>> {
+        while self.peeked.len() < FRAME_HEADER_SIZE {
+            match ready!(self.as_mut().poll_decode(cx)) {
+                Some(Ok(data)) => self.as_mut().project().peeked.put(data),
+                Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+                None => return Poll::Ready(None),
+            }
+        }
+
+        Poll::Ready(Some(Ok(FrameHeader {
+            compression: self.peeked[0] & COMPRESSED_FLAG != 0,
+            length: proto::frame_len(&self.peeked) as u32,
+        })))
+    }
 
     #[inline]
     fn max_decodable(&self) -> usize {
-        (self.buf.len() / 4) * 4
+        let len = (self.buf.len() / 4) * 4;
+
+        // Padding ends a base64 chunk, so stop after the first padded quad and decode what
+        // follows separately.
+        match self.buf[..len].iter().position(|&b| b == b'=') {
+            Some(i) => (i / 4 + 1) * 4,
+            None => len,
+        }
     }
 
     fn decode_chunk(mut self: Pin<&mut Self>) -> Result<Option<Bytes>, Status> {
@@ -75,24 +250,31 @@ impl<B> WebCall<B> {
         }
 
         let index = self.max_decodable();
+        let this = self.as_mut().project();
+        let input = this.buf.split_to(index);
+
+        // Every 4 base64 characters decode to at most 3 bytes.
+        this.decoded.resize(index / 4 * 3, 0);
+
+        let len = match general_purpose::STANDARD.decode_slice(&input, &mut this.decoded[..]) {
+            Ok(len) => len,
+            Err(e) => {
+                // Drop whatever is left so that the error is not reported again on the next poll.
+                this.buf.clear();
+                return Err(internal_error(e));
+            }
+        };
+
+        this.decoded.truncate(len);
 
-        general_purpose::STANDARD
-            .decode(self.as_mut().project().buf.split_to(index))
-            .map(|decoded| Some(Bytes::from(decoded)))
-            .map_err(internal_error)
+        Ok(Some(this.decoded.split().freeze()))
     }
-}
 
-impl<B> WebCall<B>
-where
-    B: Body<Data = Bytes>,
-    B::Error: Error,
-{
     fn poll_decode(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Option<Result<B::Data, Status>>> {
-        match self.encoding {
+        match self.encoding() {
             Encoding::Base64 => loop {
                 if let Some(bytes) = self.as_mut().decode_chunk()? {
                     return Poll::Ready(Some(Ok(bytes)));
@@ -105,6 +287,7 @@ where
                     Some(Err(e)) => return Poll::Ready(Some(Err(internal_error(e)))),
                     None => {
                         return if this.buf.has_remaining() {
+                            this.buf.clear();
                             Poll::Ready(Some(Err(internal_error("malformed base64 request"))))
                         } else {
                             Poll::Ready(None)
@@ -113,40 +296,124 @@ where
                 }
             },
 
-            Encoding::None => match ready!(self.project().inner.poll_data(cx)) {
-                Some(res) => Poll::Ready(Some(res.map_err(internal_error))),
-                None => Poll::Ready(None),
+            Encoding::None => loop {
+                match ready!(self.as_mut().project().inner.poll_data(cx)) {
+                    Some(Ok(data)) if data.is_empty() => continue,
+                    Some(Ok(data)) => {
+                        self.as_mut().track_frames(&data);
+                        return Poll::Ready(Some(Ok(data)));
+                    }
+                    Some(Err(e)) => return Poll::Ready(Some(Err(internal_error(e)))),
+                    None if self.frame_remaining > 0 || !self.buf.is_empty() => {
+                        let this = self.as_mut().project();
+                        *this.frame_remaining = 0;
+                        this.buf.clear();
+                        return Poll::Ready(Some(Err(internal_error("truncated grpc-web frame"))));
+                    }
+                    None => return Poll::Ready(None),
+                }
             },
         }
     }
 
+    /// Follows the frame headers of binary request data, buffering headers split across chunks.
+    fn track_frames(self: Pin<&mut Self>, mut data: &[u8]) {
+        let this = self.project();
+
+        while !data.is_empty() {
+            if *this.frame_remaining > 0 {
+                let len = (*this.frame_remaining).min(data.len());
+                *this.frame_remaining -= len;
+                data = &data[len..];
+                continue;
+            }
+
+            let len = (FRAME_HEADER_SIZE - this.buf.len()).min(data.len());
+            this.buf.extend_from_slice(&data[..len]);
+            data = &data[len..];
+
+            if this.buf.len() == FRAME_HEADER_SIZE {
+                let header = this.buf.split();
+                *this.frame_remaining = proto::frame_len(&header);
+            }
+        }
+    }
+}
+
+impl<B, E> WebCall<B, Response, E>
+where
+    B: Body<Data = Bytes>,
+    B::Error: Error,
+{
+    fn poll_inner_data(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<B::Data, Status>>> {
+        #[cfg(feature = "compression")]
+        if let Some(compression) = self.compression {
+            return self.poll_decompress(cx, compression);
+        }
+
+        let res = ready!(self.project().inner.poll_data(cx));
+        Poll::Ready(res.map(|res| res.map_err(internal_error)))
+    }
+
+    #[cfg(feature = "compression")]
+    fn poll_decompress(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        compression: Compression,
+    ) -> Poll<Option<Result<B::Data, Status>>> {
+        let mut this = self.project();
+
+        // Compressed messages may span several chunks, so reassemble whole frames first.
+        loop {
+            if let Some(frame) = decompress_frame(this.buf, compression)? {
+                return Poll::Ready(Some(Ok(frame)));
+            }
+
+            match ready!(this.inner.as_mut().poll_data(cx)) {
+                Some(Ok(data)) => this.buf.put(data),
+                Some(Err(e)) => return Poll::Ready(Some(Err(internal_error(e)))),
+                None if this.buf.is_empty() => return Poll::Ready(None),
+                None => {
+                    this.buf.clear();
+                    return Poll::Ready(Some(Err(internal_error("truncated grpc frame"))));
+                }
+            }
+        }
+    }
+
     fn poll_encode(
         mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Option<Result<B::Data, Status>>> {
-        let mut this = self.as_mut().project();
-
-        if let Some(mut res) = ready!(this.inner.as_mut().poll_data(cx)) {
-            if *this.encoding == Encoding::Base64 {
+        if let Some(mut res) = ready!(self.as_mut().poll_inner_data(cx)) {
+            if self.encoding == Encoding::Base64 && !self.raw_pass_through {
                 res = res.map(|b| general_purpose::STANDARD.encode(b).into());
             }
 
-            return Poll::Ready(Some(res.map_err(internal_error)));
+            return Poll::Ready(Some(res));
         }
 
+        let this = self.as_mut().project();
+
         if *this.poll_trailers {
             return match ready!(this.inner.poll_trailers(cx)) {
-                Ok(Some(map)) => {
-                    let mut frame = make_trailers_frame(&map);
+                Ok(trailers) => {
+                    *this.poll_trailers = false;
 
-                    if *this.encoding == Encoding::Base64 {
-                        frame = general_purpose::STANDARD.encode(frame).into_bytes();
-                    }
+                    match trailers.or_else(|| this.default_trailers.take()) {
+                        Some(map) => {
+                            if let Some(observer) = this.trailer_observer.as_ref() {
+                                observer(&map);
+                            }
 
-                    *this.poll_trailers = false;
-                    Poll::Ready(Some(Ok(frame.into())))
+                            Poll::Ready(Some(Ok(encode_trailers(map, *this.encoding))))
+                        }
+                        None => Poll::Ready(None),
+                    }
                 }
-                Ok(None) => Poll::Ready(None),
                 Err(e) => Poll::Ready(Some(Err(internal_error(e)))),
             };
         }
@@ -155,22 +422,46 @@ where
     }
 }
 
-impl<B> Body for WebCall<B>
+impl<B, D, E> AsRef<B> for WebCall<B, D, E> {
+    fn as_ref(&self) -> &B {
+        &self.inner
+    }
+}
+
+impl<B, E> Body for WebCall<B, Request, E>
 where
     B: Body<Data = Bytes>,
     B::Error: Error,
+    E: From<Status>,
 {
     type Data = Bytes;
-    type Error = Status;
+    type Error = E;
 
     fn poll_data(
-        self: Pin<&mut Self>,
+        mut self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
-        match self.direction {
-            Direction::Request => self.poll_decode(cx),
-            Direction::Response => self.poll_encode(cx),
+        if self.terminated {
+            return Poll::Ready(None);
         }
+
+        let res = if self.peeked.is_empty() {
+            ready!(self.as_mut().poll_decode(cx))
+        } else {
+            Some(Ok(self.as_mut().project().peeked.split().freeze()))
+        };
+
+        let this = self.project();
+        match &res {
+            Some(Ok(data)) => {
+                *this.chunks_processed += 1;
+                *this.bytes_decoded += data.len() as u64;
+            }
+            // Whatever follows an error is misaligned, end the body rather than decode it.
+            Some(Err(_)) | None => *this.terminated = true,
+        }
+
+        Poll::Ready(res.map(|res| res.map_err(E::from)))
     }
 
     fn poll_trailers(
@@ -181,6 +472,10 @@ where
     }
 
     fn is_end_stream(&self) -> bool {
+        if self.buf.has_remaining() || !self.peeked.is_empty() {
+            return false;
+        }
+
         self.inner.is_end_stream()
     }
 
@@ -189,18 +484,73 @@ where
     }
 }
 
-impl<B> Stream for WebCall<B>
+impl<B, E> Body for WebCall<B, Response, E>
 where
     B: Body<Data = Bytes>,
     B::Error: Error,
+    E: From<Status>,
+{
+    type Data = Bytes;
+    type Error = E;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        if self.terminated {
+            return Poll::Ready(None);
+        }
+
+        let res = ready!(self.as_mut().poll_encode(cx));
+
+        let this = self.project();
+        match &res {
+            Some(Ok(data)) => {
+                *this.chunks_processed += 1;
+                *this.bytes_encoded += data.len() as u64;
+            }
+            Some(Err(_)) | None => *this.terminated = true,
+        }
+
+        Poll::Ready(res.map(|res| res.map_err(E::from)))
+    }
+
+    fn poll_trailers(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap<HeaderValue>>, Self::Error>> {
+        Poll::Ready(Ok(None))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl<B, D, E> Stream for WebCall<B, D, E>
+where
+    Self: Body<Data = Bytes, Error = E>,
 {
-    type Item = Result<Bytes, Status>;
+    type Item = Result<Bytes, E>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         Body::poll_data(self, cx)
     }
 }
 
+impl<B, D, E> FusedStream for WebCall<B, D, E>
+where
+    Self: Body<Data = Bytes, Error = E>,
+{
+    fn is_terminated(&self) -> bool {
+        self.terminated
+    }
+}
+
 impl Encoding {
     pub(crate) fn from_content_type(headers: &HeaderMap) -> Encoding {
         Self::from_header(headers.get(header::CONTENT_TYPE))
@@ -210,6 +560,26 @@ impl Encoding {
         Self::from_header(headers.get(header::ACCEPT))
     }
 
+    /// Returns the response content type for the `accept` header, echoing the grpc-web variant
+    /// the client asked for.
+    pub(crate) fn accept_content_type(headers: &HeaderMap) -> &'static str {
+        let accept = headers
+            .get(header::ACCEPT)
+            .and_then(|val| val.to_str().ok())
+            .map(str::to_ascii_lowercase);
+        match accept.as_deref() {
+            Some(GRPC_WEB) => GRPC_WEB,
+            Some(GRPC_WEB_TEXT) => GRPC_WEB_TEXT,
+            Some(GRPC_WEB_TEXT_JSON) => GRPC_WEB_TEXT_JSON,
+            _ => Self::from_accept(headers).to_content_type(),
+        }
+    }
+
+    /// Returns the `accept` header value a client should send to receive this encoding.
+    pub fn to_accept_header_value(self) -> &'static str {
+        self.to_content_type()
+    }
+
     pub(crate) fn to_content_type(self) -> &'static str {
         match self {
             Encoding::Base64 => GRPC_WEB_TEXT_PROTO,
@@ -218,33 +588,302 @@ impl Encoding {
     }
 
     fn from_header(value: Option<&HeaderValue>) -> Encoding {
-        match value.and_then(|val| val.to_str().ok()) {
-            Some(GRPC_WEB_TEXT_PROTO | GRPC_WEB_TEXT) => Encoding::Base64,
+        let value = value
+            .and_then(|val| val.to_str().ok())
+            .map(str::to_ascii_lowercase);
+        match value.as_deref() {
+            Some(GRPC_WEB_TEXT_PROTO | GRPC_WEB_TEXT | GRPC_WEB_TEXT_JSON) => Encoding::Base64,
             _ => Encoding::None,
         }
     }
 }
 
 #[inline]
-fn internal_error(e: impl std::fmt::Display) -> Status {
+pub(crate) fn internal_error(e: impl std::fmt::Display) -> Status {
     Status::internal(format!("grpc-web: {e}"))
 }
 
-fn make_trailers_frame(trailers: &HeaderMap) -> Vec<u8> {
-    let trailers = trailers.iter().fold(Vec::new(), |mut acc, (key, value)| {
-        acc.put_slice(key.as_ref());
-        acc.push(b':');
-        acc.put_slice(value.as_bytes());
-        acc.put_slice(b"\r\n");
-        acc
-    });
-    let len = trailers.len();
-    assert!(u32::try_from(len).is_ok());
+/// Encodes `trailers` as a grpc-web trailers frame.
+pub(crate) fn encode_trailers(trailers: HeaderMap, encoding: Encoding) -> Bytes {
+    let frame = proto::encode_trailer_frame(&TrailerFrame { headers: trailers });
+
+    match encoding {
+        Encoding::Base64 => general_purpose::STANDARD.encode(frame).into(),
+        Encoding::None => frame,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use futures::{executor::block_on, StreamExt};
+
+    use super::*;
+
+    struct DelayedTrailers {
+        pending: bool,
+    }
+
+    impl Body for DelayedTrailers {
+        type Data = Bytes;
+        type Error = Infallible;
+
+        fn poll_data(
+            self: Pin<&mut Self>,
+            _: &mut Context<'_>,
+        ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+            Poll::Ready(None)
+        }
+
+        fn poll_trailers(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+        ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+            if self.pending {
+                self.pending = false;
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+
+            let mut trailers = HeaderMap::new();
+            trailers.insert("grpc-status", HeaderValue::from_static("0"));
+            Poll::Ready(Ok(Some(trailers)))
+        }
+    }
+
+    #[test]
+    fn constructors() {
+        let call: WebCall<_, Request> = WebCall::request(hyper::Body::empty(), Encoding::Base64);
+        assert_eq!(call.encoding(), Encoding::Base64);
+
+        let call: WebCall<_, Response> = WebCall::response(hyper::Body::empty(), Encoding::None);
+        assert_eq!(call.encoding(), Encoding::None);
+    }
+
+    #[test]
+    fn accept_header_value() {
+        for encoding in [Encoding::Base64, Encoding::None] {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                header::ACCEPT,
+                HeaderValue::from_static(encoding.to_accept_header_value()),
+            );
+
+            assert_eq!(Encoding::from_accept(&headers), encoding);
+        }
+    }
+
+    #[test]
+    fn accept_content_type_is_case_insensitive() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("APPLICATION/GRPC-WEB"),
+        );
+        assert_eq!(Encoding::accept_content_type(&headers), GRPC_WEB);
+
+        headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("Application/Grpc-Web-Text"),
+        );
+        assert_eq!(Encoding::accept_content_type(&headers), GRPC_WEB_TEXT);
+    }
+
+    #[test]
+    fn pending_trailers() {
+        let mut call = WebCall::response(DelayedTrailers { pending: true }, Encoding::None);
+
+        let frame = block_on(call.next()).unwrap().unwrap();
+        assert_eq!(&frame[..], b"\x80\x00\x00\x00\x0fgrpc-status:0\r\n");
+        assert!(block_on(call.next()).is_none());
+    }
+
+    #[test]
+    fn unary_response() {
+        let message = Bytes::from_static(b"\x00\x00\x00\x00\x02ab");
+        let mut call = WebCall::unary_response(message.clone(), Encoding::None);
+
+        assert_eq!(block_on(call.next()).unwrap().unwrap(), message);
+        let frame = block_on(call.next()).unwrap().unwrap();
+        assert_eq!(&frame[..], b"\x80\x00\x00\x00\x0fgrpc-status:0\r\n");
+        assert!(block_on(call.next()).is_none());
+
+        let mut call = WebCall::unary_response(message.clone(), Encoding::Base64);
+        let mut body = Vec::new();
+        while let Some(chunk) = block_on(call.next()) {
+            body.extend(general_purpose::STANDARD.decode(chunk.unwrap()).unwrap());
+        }
+        assert_eq!(
+            &body[..],
+            b"\x00\x00\x00\x00\x02ab\x80\x00\x00\x00\x0fgrpc-status:0\r\n"
+        );
+    }
+
+    #[test]
+    fn trailer_observer() {
+        let observed = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut call = WebCall::response(DelayedTrailers { pending: true }, Encoding::None)
+            .with_trailer_observer({
+                let observed = observed.clone();
+                move |trailers: &HeaderMap| observed.lock().unwrap().push(trailers.clone())
+            });
+
+        while block_on(call.next()).is_some() {}
+
+        let observed = observed.lock().unwrap();
+        assert_eq!(observed.len(), 1);
+        assert_eq!(observed[0]["grpc-status"], "0");
+    }
+
+    #[test]
+    fn chunks_processed() {
+        let mut call = WebCall::response(DelayedTrailers { pending: true }, Encoding::Base64);
+        let mut chunks = 0;
+
+        while let Some(chunk) = block_on(call.next()) {
+            chunk.unwrap();
+            chunks += 1;
+        }
+
+        assert_eq!(call.chunks_processed(), chunks);
+    }
+
+    #[test]
+    fn skips_empty_chunks() {
+        let chunks: Vec<Result<_, Infallible>> = vec![
+            Ok(Bytes::new()),
+            Ok(Bytes::from_static(b"\x00\x00\x00\x00\x00")),
+            Ok(Bytes::new()),
+        ];
+        let body = hyper::Body::wrap_stream(futures::stream::iter(chunks));
+        let mut call = WebCall::request(body, Encoding::None);
+
+        let data = block_on(call.next()).unwrap().unwrap();
+        assert_eq!(&data[..], b"\x00\x00\x00\x00\x00");
+        assert!(block_on(call.next()).is_none());
+    }
+
+    #[test]
+    fn base64_null_bytes() {
+        let mut call = WebCall::request(hyper::Body::from("AAAA"), Encoding::Base64);
+
+        let data = block_on(call.next()).unwrap().unwrap();
+        assert_eq!(&data[..], b"\x00\x00\x00");
+        assert!(block_on(call.next()).is_none());
+    }
+
+    #[test]
+    fn base64_padded_chunks() {
+        let chunks: Vec<Result<_, Infallible>> = vec![Ok("AA=="), Ok("AQ==")];
+        let body = hyper::Body::wrap_stream(futures::stream::iter(chunks));
+        let mut call = WebCall::request(body, Encoding::Base64);
+
+        assert_eq!(&block_on(call.next()).unwrap().unwrap()[..], b"\x00");
+        assert_eq!(&block_on(call.next()).unwrap().unwrap()[..], b"\x01");
+        assert!(block_on(call.next()).is_none());
+    }
 
-    let mut frame = Vec::with_capacity(len + FRAME_HEADER_SIZE);
-    frame.push(GRPC_WEB_TRAILERS_BIT);
-    frame.put_u32(len as u32);
-    frame.extend(trailers);
+    #[test]
+    fn base64_padding_split_across_chunks() {
+        let chunks: Vec<Result<_, Infallible>> = vec![Ok("AA="), Ok("=AQ==")];
+        let body = hyper::Body::wrap_stream(futures::stream::iter(chunks));
+        let mut call = WebCall::request(body, Encoding::Base64);
 
-    frame
+        assert_eq!(&block_on(call.next()).unwrap().unwrap()[..], b"\x00");
+        assert_eq!(&block_on(call.next()).unwrap().unwrap()[..], b"\x01");
+        assert!(block_on(call.next()).is_none());
+    }
+
+    #[test]
+    fn truncated_binary_frame() {
+        let body = hyper::Body::from(&b"\x00\x00\x00\x00\x05abc"[..]);
+        let mut call = WebCall::request(body, Encoding::None);
+
+        assert!(block_on(call.next()).unwrap().is_ok());
+        assert!(block_on(call.next()).unwrap().is_err());
+        assert!(block_on(call.next()).is_none());
+    }
+
+    #[test]
+    fn empty_base64_request() {
+        let mut call = WebCall::request(hyper::Body::empty(), Encoding::Base64);
+
+        assert!(block_on(call.next()).is_none());
+        assert_eq!(call.bytes_decoded(), 0);
+    }
+
+    #[test]
+    fn fused() {
+        let mut call = WebCall::response(DelayedTrailers { pending: false }, Encoding::None);
+
+        while block_on(call.next()).is_some() {}
+
+        assert!(call.is_terminated());
+        assert!(block_on(call.next()).is_none());
+    }
+
+    #[test]
+    fn with_error_type() {
+        let mut call = WebCall::request(hyper::Body::from("!!!!"), Encoding::Base64)
+            .with_error_type::<Box<dyn Error + Send + Sync>>();
+
+        let err = block_on(call.next()).unwrap().unwrap_err();
+        assert!(err.to_string().contains("grpc-web"));
+    }
+
+    #[test]
+    fn ends_after_error() {
+        let chunks: Vec<Result<_, Infallible>> = vec![Ok("!!!!"), Ok("AAAA")];
+        let body = hyper::Body::wrap_stream(futures::stream::iter(chunks));
+        let mut call = WebCall::request(body, Encoding::Base64);
+
+        assert!(block_on(call.next()).unwrap().is_err());
+        assert!(call.is_terminated());
+        assert!(block_on(call.next()).is_none());
+    }
+
+    #[test]
+    fn binary_response_size_hint() {
+        let call = WebCall::response(hyper::Body::from("hello"), Encoding::None);
+        assert_eq!(call.size_hint().exact(), Some(5));
+    }
+
+    #[test]
+    fn unpin() {
+        fn assert_unpin<T: Unpin>() {}
+
+        assert_unpin::<WebCall<hyper::Body, Request>>();
+        assert_unpin::<WebCall<std::io::Cursor<Vec<u8>>, Response>>();
+    }
+
+    #[test]
+    fn peek_first_frame() {
+        let body = hyper::Body::from(&b"\x01\x00\x00\x00\x02ab"[..]);
+        let mut call = WebCall::request(body, Encoding::None);
+
+        let header = block_on(futures::future::poll_fn(|cx| {
+            Pin::new(&mut call).peek_first_frame(cx)
+        }))
+        .unwrap()
+        .unwrap();
+        assert_eq!(
+            header,
+            FrameHeader {
+                compression: true,
+                length: 2
+            }
+        );
+
+        let data = block_on(call.next()).unwrap().unwrap();
+        assert_eq!(&data[..], b"\x01\x00\x00\x00\x02ab");
+        assert!(block_on(call.next()).is_none());
+    }
+
+    #[test]
+    fn into_inner() {
+        let call = WebCall::response(DelayedTrailers { pending: true }, Encoding::None);
+        assert!(call.as_ref().pending);
+        assert!(call.into_inner().pending);
+    }
 }
@@ -0,0 +1,179 @@
+use std::io::{self, Read};
+
+use bytes::{Bytes, BytesMut};
+use flate2::read::{GzDecoder, ZlibDecoder};
+use http::HeaderMap;
+use volo_grpc::Status;
+
+use crate::{
+    codec::internal_error,
+    proto::{self, DataFrame, COMPRESSED_FLAG},
+};
+
+const GRPC_ENCODING: &str = "grpc-encoding";
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub(crate) enum Compression {
+    Gzip,
+    Deflate,
+}
+
+impl Compression {
+    /// Removes and returns the `grpc-encoding` of a response if it is one we can decompress.
+    pub(crate) fn take(headers: &mut HeaderMap) -> Option<Compression> {
+        let compression = match headers.get(GRPC_ENCODING).map(|v| v.as_bytes()) {
+            Some(b"gzip") => Compression::Gzip,
+            Some(b"deflate") => Compression::Deflate,
+            _ => return None,
+        };
+
+        headers.remove(GRPC_ENCODING);
+        Some(compression)
+    }
+
+    fn decompress(self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(data.len() * 2);
+
+        match self {
+            Compression::Gzip => GzDecoder::new(data).read_to_end(&mut out)?,
+            Compression::Deflate => ZlibDecoder::new(data).read_to_end(&mut out)?,
+        };
+
+        Ok(out)
+    }
+}
+
+/// Splits the next complete frame off `buf`, decompressing its message if needed.
+pub(crate) fn decompress_frame(
+    buf: &mut BytesMut,
+    compression: Compression,
+) -> Result<Option<Bytes>, Status> {
+    let frame = match proto::decode_data_frame(buf) {
+        Some(frame) => frame,
+        None => return Ok(None),
+    };
+
+    if frame.flags & COMPRESSED_FLAG == 0 {
+        return Ok(Some(proto::encode_data_frame(&frame)));
+    }
+
+    let message = compression
+        .decompress(&frame.body)
+        .map_err(internal_error)?;
+    // Report oversized messages rather than panicking in `encode_data_frame`.
+    u32::try_from(message.len()).map_err(internal_error)?;
+
+    Ok(Some(proto::encode_data_frame(&DataFrame {
+        flags: frame.flags & !COMPRESSED_FLAG,
+        body: message.into(),
+    })))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, io::Write};
+
+    use base64::{engine::general_purpose, Engine};
+    use flate2::write::{GzEncoder, ZlibEncoder};
+    use futures::{executor::block_on, StreamExt};
+    use http::HeaderValue;
+
+    use super::*;
+    use crate::{Encoding, WebCall};
+
+    fn frame(flags: u8, message: &[u8]) -> Bytes {
+        proto::encode_data_frame(&DataFrame {
+            flags,
+            body: Bytes::copy_from_slice(message),
+        })
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn deflate(data: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn compressed_frames() {
+        for (compression, message) in [
+            (Compression::Gzip, gzip(b"hello")),
+            (Compression::Deflate, deflate(b"hello")),
+        ] {
+            let mut buf = BytesMut::from(&frame(COMPRESSED_FLAG, &message)[..]);
+
+            let out = decompress_frame(&mut buf, compression).unwrap().unwrap();
+            assert_eq!(out, frame(0, b"hello"));
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn uncompressed_frame() {
+        let mut buf = BytesMut::from(&frame(0, b"hello")[..]);
+
+        let out = decompress_frame(&mut buf, Compression::Gzip)
+            .unwrap()
+            .unwrap();
+        assert_eq!(out, frame(0, b"hello"));
+    }
+
+    #[test]
+    fn frame_split_across_chunks() {
+        let data = frame(COMPRESSED_FLAG, &gzip(b"hello"));
+        let (head, tail) = data.split_at(3);
+        let chunks: Vec<Result<_, Infallible>> = vec![
+            Ok(Bytes::copy_from_slice(head)),
+            Ok(Bytes::copy_from_slice(tail)),
+        ];
+        let body = hyper::Body::wrap_stream(futures::stream::iter(chunks));
+        let mut call =
+            WebCall::response(body, Encoding::None).with_compression(Some(Compression::Gzip));
+
+        assert_eq!(block_on(call.next()).unwrap().unwrap(), frame(0, b"hello"));
+    }
+
+    #[test]
+    fn truncated_frame() {
+        let data = frame(COMPRESSED_FLAG, &gzip(b"hello"));
+        let body = hyper::Body::from(data.slice(..data.len() - 1));
+        let mut call =
+            WebCall::response(body, Encoding::None).with_compression(Some(Compression::Gzip));
+
+        assert!(block_on(call.next()).unwrap().is_err());
+    }
+
+    #[test]
+    fn unknown_encoding() {
+        let mut headers = HeaderMap::new();
+        headers.insert(GRPC_ENCODING, HeaderValue::from_static("snappy"));
+
+        assert_eq!(Compression::take(&mut headers), None);
+        assert_eq!(headers[GRPC_ENCODING], "snappy");
+
+        headers.insert(GRPC_ENCODING, HeaderValue::from_static("gzip"));
+        assert_eq!(Compression::take(&mut headers), Some(Compression::Gzip));
+        assert!(!headers.contains_key(GRPC_ENCODING));
+    }
+
+    #[test]
+    fn base64_output() {
+        let body = hyper::Body::from(frame(COMPRESSED_FLAG, &gzip(b"hello")));
+        let mut call =
+            WebCall::response(body, Encoding::Base64).with_compression(Some(Compression::Gzip));
+
+        let data = block_on(call.next()).unwrap().unwrap();
+        assert_eq!(
+            &data[..],
+            general_purpose::STANDARD
+                .encode(frame(0, b"hello"))
+                .as_bytes()
+        );
+    }
+}
@@ -1,11 +1,16 @@
 use std::{
-    collections::{BTreeSet, HashSet},
-    convert::TryFrom,
-    fmt::Debug,
+    collections::{BTreeSet, HashMap, HashSet},
+    convert::{Infallible, TryFrom},
+    env::VarError,
+    fmt::{self, Debug},
+    num::ParseIntError,
+    ops::Deref,
+    str::{FromStr, ParseBoolError},
     sync::Arc,
     time::Duration,
 };
 
+use dashmap::DashSet;
 pub(crate) use http::header::{
     ACCESS_CONTROL_ALLOW_CREDENTIALS as ALLOW_CREDENTIALS,
     ACCESS_CONTROL_ALLOW_HEADERS as ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS as ALLOW_METHODS,
@@ -15,25 +20,167 @@ pub(crate) use http::header::{
 };
 use http::{
     header::{self, HeaderName},
-    HeaderMap, HeaderValue, Method,
+    uri::Authority,
+    HeaderMap, HeaderValue, Method, StatusCode, Uri,
 };
 use tracing::debug;
 
+use crate::X_FORWARDED_PROTO;
+
+// Bounds the memory spent remembering rejected origins, which are client controlled.
+const MAX_TRACKED_REJECTIONS: usize = 1024;
+
 const DEFAULT_MAX_AGE: Duration = Duration::from_secs(24 * 60 * 60);
 
-const DEFAULT_EXPOSED_HEADERS: [&str; 2] = ["grpc-status", "grpc-message"];
+const DEFAULT_EXPOSED_HEADERS: [&str; 3] =
+    ["grpc-status", "grpc-message", "grpc-status-details-bin"];
+const TIMING_ALLOW_ORIGIN: &str = "timing-allow-origin";
+const DEFAULT_LATENCY_HEADER: &str = "x-grpc-web-latency-ms";
 const DEFAULT_ALLOWED_METHODS: &[Method; 2] = &[Method::POST, Method::OPTIONS];
 
+// Request headers browsers never let scripts set, see
+// https://fetch.spec.whatwg.org/#forbidden-request-header
+const FORBIDDEN_REQUEST_HEADERS: &[&str] = &[
+    "accept-charset",
+    "accept-encoding",
+    "access-control-request-headers",
+    "access-control-request-method",
+    "connection",
+    "content-length",
+    "cookie",
+    "cookie2",
+    "date",
+    "dnt",
+    "expect",
+    "host",
+    "keep-alive",
+    "origin",
+    "referer",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+    "via",
+];
+const FORBIDDEN_REQUEST_HEADER_PREFIXES: &[&str] = &["proxy-", "sec-"];
+
 #[derive(Debug, PartialEq)]
 pub(crate) enum Error {
     OriginNotAllowed,
     MethodNotAllowed,
+    ForbiddenRequestHeader,
+    InsecureOrigin,
+}
+
+/// An inconsistent [`Config`], reported by [`Cors::try_new`] or [`cors::Builder::build`].
+///
+/// [`cors::Builder::build`]: crate::cors::Builder::build
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ConfigError {
+    /// Credentials are allowed for any origin.
+    CredentialsWithWildcard,
+    /// A configured header value is not valid.
+    InvalidHeaderValue(header::InvalidHeaderValue),
+    /// A configured header name is not valid.
+    InvalidHeaderName(header::InvalidHeaderName),
+    /// A configured origin is not valid.
+    InvalidOrigin(InvalidOriginError),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::CredentialsWithWildcard => {
+                f.write_str("credentials cannot be allowed for any origin")
+            }
+            ConfigError::InvalidHeaderValue(e) => write!(f, "invalid header value: {e}"),
+            ConfigError::InvalidHeaderName(e) => write!(f, "invalid header name: {e}"),
+            ConfigError::InvalidOrigin(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// An error returned by [`Config::allow_origins`] for an origin that cannot be used.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum InvalidOriginError {
+    /// The origin is not a valid header value.
+    InvalidHeaderValue(header::InvalidHeaderValue),
+    /// The origin is not a scheme and an authority, such as `https://example.com:8080`.
+    Malformed(HeaderValue),
+}
+
+impl fmt::Display for InvalidOriginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidOriginError::InvalidHeaderValue(e) => write!(f, "invalid origin: {e}"),
+            InvalidOriginError::Malformed(origin) => write!(f, "malformed origin {origin:?}"),
+        }
+    }
+}
+
+impl std::error::Error for InvalidOriginError {}
+
+impl From<header::InvalidHeaderValue> for InvalidOriginError {
+    fn from(e: header::InvalidHeaderValue) -> Self {
+        InvalidOriginError::InvalidHeaderValue(e)
+    }
+}
+
+impl From<Infallible> for InvalidOriginError {
+    fn from(e: Infallible) -> Self {
+        match e {}
+    }
+}
+
+/// An environment variable read by [`Config::from_env`] that could not be parsed.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ConfigFromEnvError {
+    /// A variable is not valid unicode.
+    NotUnicode(&'static str),
+    /// `GRPC_WEB_ALLOWED_ORIGINS` contains an invalid origin.
+    AllowedOrigins(InvalidOriginError),
+    /// `GRPC_WEB_EXPOSED_HEADERS` contains an invalid header name.
+    ExposedHeaders(header::InvalidHeaderName),
+    /// `GRPC_WEB_MAX_AGE_SECONDS` is not a number of seconds.
+    MaxAge(ParseIntError),
+    /// `GRPC_WEB_ALLOW_CREDENTIALS` is neither `true` nor `false`.
+    AllowCredentials(ParseBoolError),
+    /// `GRPC_WEB_ALLOW_CREDENTIALS` is `true` but `GRPC_WEB_ALLOWED_ORIGINS` is unset, which
+    /// would allow credentials for any origin.
+    CredentialsWithoutOrigins,
 }
 
+impl fmt::Display for ConfigFromEnvError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigFromEnvError::NotUnicode(name) => write!(f, "{name} is not valid unicode"),
+            ConfigFromEnvError::AllowedOrigins(e) => write!(f, "GRPC_WEB_ALLOWED_ORIGINS: {e}"),
+            ConfigFromEnvError::ExposedHeaders(e) => write!(f, "GRPC_WEB_EXPOSED_HEADERS: {e}"),
+            ConfigFromEnvError::MaxAge(e) => write!(f, "GRPC_WEB_MAX_AGE_SECONDS: {e}"),
+            ConfigFromEnvError::AllowCredentials(e) => {
+                write!(f, "GRPC_WEB_ALLOW_CREDENTIALS: {e}")
+            }
+            ConfigFromEnvError::CredentialsWithoutOrigins => {
+                f.write_str("GRPC_WEB_ALLOW_CREDENTIALS requires GRPC_WEB_ALLOWED_ORIGINS")
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigFromEnvError {}
+
 #[derive(Debug, Clone)]
 pub(crate) enum AllowedOrigins {
     Any,
     Only(BTreeSet<HeaderValue>),
+    #[cfg(feature = "regex")]
+    Regex(Arc<regex::Regex>),
+    Custom(Arc<[Box<dyn OriginMatcher>]>),
 }
 
 impl AllowedOrigins {
@@ -41,16 +188,143 @@ impl AllowedOrigins {
         match self {
             AllowedOrigins::Any => true,
             AllowedOrigins::Only(origins) => origins.contains(origin),
+            #[cfg(feature = "regex")]
+            AllowedOrigins::Regex(regex) => origin
+                .to_str()
+                .map_or(false, |origin| regex.is_match(origin)),
+            AllowedOrigins::Custom(matchers) => matchers.iter().any(|m| m.is_match(origin)),
         }
     }
 }
 
+/// Decides whether requests from an origin are allowed, see [`Config::allow_patterns`].
+pub trait OriginMatcher: Send + Sync {
+    fn is_match(&self, origin: &HeaderValue) -> bool;
+}
+
+impl Debug for dyn OriginMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OriginMatcher")
+    }
+}
+
+/// Matches a single origin, such as `https://example.com`.
+#[derive(Clone, Debug)]
+pub struct ExactOrigin(pub HeaderValue);
+
+impl OriginMatcher for ExactOrigin {
+    fn is_match(&self, origin: &HeaderValue) -> bool {
+        *origin == self.0
+    }
+}
+
+/// Matches origins starting with a prefix, such as `http://localhost:`.
+#[derive(Clone, Debug)]
+pub struct PrefixOrigin(pub String);
+
+impl OriginMatcher for PrefixOrigin {
+    fn is_match(&self, origin: &HeaderValue) -> bool {
+        origin.as_bytes().starts_with(self.0.as_bytes())
+    }
+}
+
+/// Matches origins against a pattern where `*` stands for any sequence of characters, such as
+/// `https://*.example.com`.
+#[derive(Clone, Debug)]
+pub struct GlobOrigin(pub String);
+
+impl OriginMatcher for GlobOrigin {
+    fn is_match(&self, origin: &HeaderValue) -> bool {
+        let mut parts = self.0.split('*');
+        let first = parts.next().unwrap_or_default();
+        let Some(mut rest) = origin.to_str().ok().and_then(|o| o.strip_prefix(first)) else {
+            return false;
+        };
+
+        let Some(last) = parts.next_back() else {
+            return rest.is_empty();
+        };
+
+        for part in parts {
+            match rest.find(part) {
+                Some(i) => rest = &rest[i + part.len()..],
+                None => return false,
+            }
+        }
+
+        rest.ends_with(last)
+    }
+}
+
+/// Matches whole origins against a regular expression, such as `https://[a-z]+\.example\.com`.
+#[cfg(feature = "regex")]
+#[derive(Clone, Debug)]
+pub struct RegexOrigin(regex::Regex);
+
+#[cfg(feature = "regex")]
+impl RegexOrigin {
+    pub fn new(pattern: &str) -> Result<Self, regex::Error> {
+        anchored_regex(pattern).map(RegexOrigin)
+    }
+}
+
+#[cfg(feature = "regex")]
+impl OriginMatcher for RegexOrigin {
+    fn is_match(&self, origin: &HeaderValue) -> bool {
+        origin
+            .to_str()
+            .map_or(false, |origin| self.0.is_match(origin))
+    }
+}
+
+pub(crate) type TrailerObserver = dyn Fn(&HeaderMap) + Send + Sync;
+pub(crate) type MethodFallback = dyn Fn(Method) -> StatusCode + Send + Sync;
+
+/// A user supplied closure stored in the configuration.
+pub(crate) struct Callback<F: ?Sized>(pub(crate) Arc<F>);
+
+impl<F: ?Sized> Clone for Callback<F> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<F: ?Sized> Deref for Callback<F> {
+    type Target = F;
+
+    fn deref(&self) -> &F {
+        &self.0
+    }
+}
+
+impl<F: ?Sized> Debug for Callback<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Callback")
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Config {
     allowed_origins: AllowedOrigins,
     exposed_headers: HashSet<HeaderName>,
     max_age: Option<Duration>,
+    max_age_per_origin: HashMap<HeaderValue, Duration>,
+    #[cfg(feature = "jitter")]
+    max_age_jitter: Duration,
     allow_credentials: bool,
+    allow_patch: bool,
+    trailer_observer: Option<Callback<TrailerObserver>>,
+    method_fallback: Option<Callback<MethodFallback>>,
+    preflight_status: StatusCode,
+    require_tls: bool,
+    skip_same_origin_cors: bool,
+    passthrough_non_grpc_web: bool,
+    debug_cors_errors: bool,
+    health_check_path: Option<String>,
+    debug_latency_header: bool,
+    latency_header_name: HeaderName,
+    request_timeout: Option<Duration>,
+    timing_allow_origin: bool,
 }
 
 impl Config {
@@ -63,29 +337,76 @@ impl Config {
                 .map(HeaderName::from_static)
                 .collect(),
             max_age: Some(DEFAULT_MAX_AGE),
-            allow_credentials: true,
+            max_age_per_origin: HashMap::new(),
+            #[cfg(feature = "jitter")]
+            max_age_jitter: Duration::ZERO,
+            allow_credentials: false,
+            allow_patch: false,
+            trailer_observer: None,
+            method_fallback: None,
+            preflight_status: StatusCode::NO_CONTENT,
+            require_tls: false,
+            skip_same_origin_cors: false,
+            passthrough_non_grpc_web: false,
+            debug_cors_errors: false,
+            health_check_path: None,
+            debug_latency_header: false,
+            latency_header_name: HeaderName::from_static(DEFAULT_LATENCY_HEADER),
+            request_timeout: None,
+            timing_allow_origin: false,
         }
     }
 
+    /// Only allows requests from `origins`, failing if any of them is invalid.
     #[allow(clippy::mutable_key_type)]
-    #[must_use]
-    pub fn allow_origins<I>(self, origins: I) -> Self
+    pub fn allow_origins<I>(self, origins: I) -> Result<Self, InvalidOriginError>
     where
         I: IntoIterator,
         HeaderValue: TryFrom<I::Item>,
-        <HeaderValue as TryFrom<I::Item>>::Error: Debug,
+        <HeaderValue as TryFrom<I::Item>>::Error: Into<InvalidOriginError>,
     {
         let origins = origins
             .into_iter()
-            .map(|v| TryFrom::try_from(v).expect("invalid origin"))
-            .collect();
+            .map(|v| parse_origin(HeaderValue::try_from(v).map_err(Into::into)?))
+            .collect::<Result<_, _>>()?;
 
-        Self {
+        Ok(Self {
             allowed_origins: AllowedOrigins::Only(origins),
             ..self
+        })
+    }
+
+    /// Only allows requests from origins matching `pattern` as a whole.
+    #[cfg(feature = "regex")]
+    pub fn allow_origins_regex(self, pattern: &str) -> Result<Self, regex::Error> {
+        let regex = anchored_regex(pattern)?;
+
+        Ok(Self {
+            allowed_origins: AllowedOrigins::Regex(Arc::new(regex)),
+            ..self
+        })
+    }
+
+    /// Only allows requests from origins matched by any of `matchers`.
+    #[must_use]
+    pub fn allow_patterns(self, matchers: Vec<Box<dyn OriginMatcher>>) -> Self {
+        Self {
+            allowed_origins: AllowedOrigins::Custom(matchers.into()),
+            ..self
         }
     }
 
+    /// Like [`Config::allow_origins`], but panics if any of `origins` is invalid.
+    #[must_use]
+    pub fn allow_origins_unchecked<I>(self, origins: I) -> Self
+    where
+        I: IntoIterator,
+        HeaderValue: TryFrom<I::Item>,
+        <HeaderValue as TryFrom<I::Item>>::Error: Into<InvalidOriginError>,
+    {
+        self.allow_origins(origins).expect("invalid origin")
+    }
+
     #[must_use]
     pub fn expose_headers<I>(mut self, headers: I) -> Self
     where
@@ -101,6 +422,15 @@ impl Config {
         self
     }
 
+    /// Removes every exposed header, including the defaults, so that
+    /// `access-control-expose-headers` is omitted unless [`Config::expose_headers`] is called
+    /// again.
+    #[must_use]
+    pub fn clear_exposed_headers(mut self) -> Self {
+        self.exposed_headers.clear();
+        self
+    }
+
     #[must_use]
     pub fn max_age<T: Into<Option<Duration>>>(self, max_age: T) -> Self {
         Self {
@@ -109,6 +439,41 @@ impl Config {
         }
     }
 
+    /// Sets the preflight `max-age` in seconds. `0` omits the header, like `max_age(None)`.
+    #[must_use]
+    pub fn max_age_seconds(self, secs: u32) -> Self {
+        let max_age = (secs > 0).then(|| Duration::from_secs(secs.into()));
+        self.max_age(max_age)
+    }
+
+    /// Overrides the preflight `max-age` for specific origins, failing if any of them is invalid.
+    #[allow(clippy::mutable_key_type)]
+    pub fn max_age_per_origin<'a, I>(
+        mut self,
+        origins_and_ages: I,
+    ) -> Result<Self, InvalidOriginError>
+    where
+        I: IntoIterator<Item = (&'a str, Duration)>,
+    {
+        for (origin, max_age) in origins_and_ages {
+            let origin = parse_origin(HeaderValue::from_str(origin)?)?;
+            self.max_age_per_origin.insert(origin, max_age);
+        }
+
+        Ok(self)
+    }
+
+    /// Randomizes the preflight `max-age` within `[base, base + jitter]` on every response.
+    #[cfg(feature = "jitter")]
+    #[must_use]
+    pub fn max_age_jitter(self, base: Duration, jitter: Duration) -> Self {
+        Self {
+            max_age: Some(base),
+            max_age_jitter: jitter,
+            ..self
+        }
+    }
+
     #[must_use]
     pub fn allow_credentials(self, allow_credentials: bool) -> Self {
         Self {
@@ -116,6 +481,192 @@ impl Config {
             ..self
         }
     }
+
+    /// Accepts grpc-web calls sent with `PATCH` in addition to `POST`.
+    #[must_use]
+    pub fn allow_patch(self, allow_patch: bool) -> Self {
+        Self {
+            allow_patch,
+            ..self
+        }
+    }
+
+    /// Calls `f` with the trailers of every grpc-web response, right before they are encoded.
+    #[must_use]
+    pub fn observe_trailers<F>(self, f: F) -> Self
+    where
+        F: Fn(&HeaderMap) + Send + Sync + 'static,
+    {
+        Self {
+            trailer_observer: Some(Callback(Arc::new(f))),
+            ..self
+        }
+    }
+
+    /// Calls `f` to pick the response status for grpc-web requests with a method other than
+    /// `POST` (or `PATCH`, if allowed). Such requests are rejected with `405 Method Not Allowed`
+    /// by default.
+    #[must_use]
+    pub fn on_unrecognized_method<F>(self, f: F) -> Self
+    where
+        F: Fn(Method) -> StatusCode + Send + Sync + 'static,
+    {
+        Self {
+            method_fallback: Some(Callback(Arc::new(f))),
+            ..self
+        }
+    }
+
+    /// Sets the status of successful preflight responses, either `204 No Content` (the default)
+    /// or `200 OK` for clients that mishandle `204`.
+    #[must_use]
+    pub fn preflight_success_status(self, status: StatusCode) -> Self {
+        assert!(
+            matches!(status, StatusCode::OK | StatusCode::NO_CONTENT),
+            "invalid preflight status {status}"
+        );
+
+        Self {
+            preflight_status: status,
+            ..self
+        }
+    }
+
+    /// Rejects non-`https` origins and asks browsers to only use HTTPS via
+    /// `Strict-Transport-Security`.
+    #[must_use]
+    pub fn require_tls(self, require_tls: bool) -> Self {
+        Self {
+            require_tls,
+            ..self
+        }
+    }
+
+    /// Omits CORS headers from in-flight requests sent to the origin they come from. Such requests
+    /// are still checked against the allowed origins and [`Config::require_tls`].
+    ///
+    /// The scheme of a request is read from its uri or `x-forwarded-proto`: requests with neither
+    /// are never considered same origin.
+    #[must_use]
+    pub fn skip_same_origin_cors(self, skip_same_origin_cors: bool) -> Self {
+        Self {
+            skip_same_origin_cors,
+            ..self
+        }
+    }
+
+    /// Forwards HTTP/1.1 requests that are not grpc-web to the inner service instead of
+    /// rejecting them with `400 Bad Request`.
+    #[must_use]
+    pub fn passthrough_non_grpc_web(self, passthrough_non_grpc_web: bool) -> Self {
+        Self {
+            passthrough_non_grpc_web,
+            ..self
+        }
+    }
+
+    /// Adds `access-control-allow-methods` to rejected preflights, so that browser developer tools
+    /// show more than a bare `403 Forbidden`. Disabled by default, as it discloses configuration.
+    #[must_use]
+    pub fn debug_cors_errors(self, debug_cors_errors: bool) -> Self {
+        Self {
+            debug_cors_errors,
+            ..self
+        }
+    }
+
+    /// Answers `GET` requests on `path` with `200 OK` and `{"status":"serving"}`, without calling
+    /// the inner service.
+    #[must_use]
+    pub fn health_check_path(self, path: &str) -> Self {
+        Self {
+            health_check_path: Some(path.to_owned()),
+            ..self
+        }
+    }
+
+    /// Adds the time spent handling each request, in milliseconds, to its response. Meant for
+    /// development, disabled by default.
+    #[must_use]
+    pub fn debug_latency_header(self, debug_latency_header: bool) -> Self {
+        Self {
+            debug_latency_header,
+            ..self
+        }
+    }
+
+    /// Sets the name of the header added by [`Config::debug_latency_header`], which defaults to
+    /// `x-grpc-web-latency-ms`.
+    #[must_use]
+    pub fn latency_header_name(self, latency_header_name: HeaderName) -> Self {
+        Self {
+            latency_header_name,
+            ..self
+        }
+    }
+
+    /// Fails grpc-web calls whose response headers take longer than `timeout` with
+    /// `DEADLINE_EXCEEDED`, reported in the trailers of a `200 OK` response as grpc-web clients
+    /// expect.
+    ///
+    /// Only the wait for the response headers is limited: once they are received, a streaming
+    /// response body may still run for as long as the inner service keeps it open.
+    #[must_use]
+    pub fn request_timeout(self, timeout: Duration) -> Self {
+        Self {
+            request_timeout: Some(timeout),
+            ..self
+        }
+    }
+
+    /// Adds `timing-allow-origin` to in-flight responses for allowed origins, so that pages can
+    /// read their Resource Timing details.
+    #[must_use]
+    pub fn timing_allow_origin(self, timing_allow_origin: bool) -> Self {
+        Self {
+            timing_allow_origin,
+            ..self
+        }
+    }
+
+    /// Builds a configuration from `GRPC_WEB_ALLOWED_ORIGINS` and `GRPC_WEB_EXPOSED_HEADERS`
+    /// (both comma separated), `GRPC_WEB_MAX_AGE_SECONDS` and `GRPC_WEB_ALLOW_CREDENTIALS`.
+    /// Unset variables keep their defaults, and allowing credentials requires allowed origins.
+    pub fn from_env() -> Result<Self, ConfigFromEnvError> {
+        let mut config = Config::new();
+
+        if let Some(origins) = env_var("GRPC_WEB_ALLOWED_ORIGINS")? {
+            config = config
+                .allow_origins(split_list(&origins))
+                .map_err(ConfigFromEnvError::AllowedOrigins)?;
+        }
+
+        if let Some(headers) = env_var("GRPC_WEB_EXPOSED_HEADERS")? {
+            let headers = split_list(&headers)
+                .map(HeaderName::from_str)
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(ConfigFromEnvError::ExposedHeaders)?;
+            config = config.expose_headers(headers);
+        }
+
+        if let Some(max_age) = env_var("GRPC_WEB_MAX_AGE_SECONDS")? {
+            let max_age = max_age.trim().parse().map_err(ConfigFromEnvError::MaxAge)?;
+            config = config.max_age(Duration::from_secs(max_age));
+        }
+
+        if let Some(allow_credentials) = env_var("GRPC_WEB_ALLOW_CREDENTIALS")? {
+            let allow_credentials = allow_credentials
+                .trim()
+                .parse()
+                .map_err(ConfigFromEnvError::AllowCredentials)?;
+            if allow_credentials && matches!(config.allowed_origins, AllowedOrigins::Any) {
+                return Err(ConfigFromEnvError::CredentialsWithoutOrigins);
+            }
+            config = config.allow_credentials(allow_credentials);
+        }
+
+        Ok(config)
+    }
 }
 
 impl Default for Config {
@@ -124,22 +675,140 @@ impl Default for Config {
     }
 }
 
+impl fmt::Display for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.allowed_origins {
+            AllowedOrigins::Any => f.write_str("cors=any")?,
+            AllowedOrigins::Only(origins) => {
+                let origins: Vec<_> = origins
+                    .iter()
+                    .map(|o| String::from_utf8_lossy(o.as_bytes()))
+                    .collect();
+                write!(f, "cors=[{}]", origins.join(", "))?;
+            }
+            #[cfg(feature = "regex")]
+            AllowedOrigins::Regex(regex) => write!(f, "cors=/{regex}/")?,
+            AllowedOrigins::Custom(matchers) => write!(f, "cors=custom({})", matchers.len())?,
+        }
+
+        let mut exposed: Vec<_> = self
+            .exposed_headers
+            .iter()
+            .map(HeaderName::as_str)
+            .collect();
+        exposed.sort_unstable();
+        write!(f, ", expose=[{}]", exposed.join(", "))?;
+
+        match self.max_age {
+            Some(max_age) => write!(f, ", max_age={}s", max_age.as_secs()),
+            None => f.write_str(", max_age=none"),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Cors {
     inner: Arc<Config>,
+    // Every header of `common_headers` but `access-control-allow-origin`, built once.
+    common_headers: Arc<HeaderMap>,
+    rejected_origins: Arc<DashSet<String>>,
+}
+
+impl fmt::Display for Cors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.inner, f)
+    }
 }
 
 impl Cors {
+    /// Creates a new `Cors`, panicking if `config` is inconsistent. See [`Cors::try_new`].
     pub fn new(config: Config) -> Self {
-        Self {
+        Self::try_new(config).expect("invalid grpc-web CORS configuration")
+    }
+
+    pub fn try_new(config: Config) -> Result<Self, ConfigError> {
+        if config.allow_credentials && matches!(config.allowed_origins, AllowedOrigins::Any) {
+            return Err(ConfigError::CredentialsWithWildcard);
+        }
+
+        let mut common_headers = HeaderMap::new();
+        if !config.exposed_headers.is_empty() {
+            let exposed_headers = join_header_value(&config.exposed_headers)
+                .map_err(ConfigError::InvalidHeaderValue)?;
+            common_headers.insert(EXPOSE_HEADERS, exposed_headers);
+        }
+
+        if config.allow_credentials {
+            common_headers.insert(ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+        }
+
+        if config.require_tls {
+            common_headers.insert(
+                header::STRICT_TRANSPORT_SECURITY,
+                HeaderValue::from_static("max-age=31536000; includeSubDomains"),
+            );
+        }
+
+        Ok(Self {
+            inner: Arc::new(config),
+            common_headers: Arc::new(common_headers),
+            rejected_origins: Arc::new(DashSet::new()),
+        })
+    }
+
+    /// Returns a `Cors` sharing this configuration, for use by several [`WebLayer`]s. This is a
+    /// cheap reference count increment, not a deep copy.
+    ///
+    /// [`WebLayer`]: crate::WebLayer
+    pub fn clone_ref(&self) -> Cors {
+        self.clone()
+    }
+
+    /// Returns a `Cors` with the CORS policy of `self`, such as its allowed origins and exposed
+    /// headers, and every other setting of `server`.
+    pub(crate) fn with_server_settings(&self, server: &Cors) -> Cors {
+        let server = &server.inner;
+        let config = Config {
+            allow_patch: server.allow_patch,
+            trailer_observer: server.trailer_observer.clone(),
+            method_fallback: server.method_fallback.clone(),
+            passthrough_non_grpc_web: server.passthrough_non_grpc_web,
+            health_check_path: server.health_check_path.clone(),
+            debug_latency_header: server.debug_latency_header,
+            latency_header_name: server.latency_header_name.clone(),
+            request_timeout: server.request_timeout,
+            ..Config::clone(&self.inner)
+        };
+
+        Cors {
             inner: Arc::new(config),
+            common_headers: self.common_headers.clone(),
+            rejected_origins: self.rejected_origins.clone(),
         }
     }
 
+    /// Returns `true` the first time `origin` is rejected as not allowed.
+    pub(crate) fn first_rejection(&self, origin: &HeaderValue, error: &Error) -> bool {
+        if *error != Error::OriginNotAllowed
+            || self.rejected_origins.len() >= MAX_TRACKED_REJECTIONS
+        {
+            return false;
+        }
+
+        self.rejected_origins
+            .insert(String::from_utf8_lossy(origin.as_bytes()).into_owned())
+    }
+
+    /// Only the first `origin` header is considered if a request has several.
     pub(crate) fn simple(&self, headers: &HeaderMap) -> Result<HeaderMap, Error> {
         match headers.get(header::ORIGIN) {
+            Some(origin) if self.is_insecure(origin) => Err(Error::InsecureOrigin),
             Some(origin) if self.inner.allowed_origins.is_allowed(origin) => {
-                Ok(self.common_headers(origin.clone()))
+                let mut headers = self.common_headers(origin.clone());
+                if self.inner.timing_allow_origin {
+                    headers.insert(TIMING_ALLOW_ORIGIN, origin.clone());
+                }
+                Ok(headers)
             }
             Some(_) => Err(Error::OriginNotAllowed),
             None => Ok(HeaderMap::new()),
@@ -152,53 +821,279 @@ impl Cors {
         origin: &HeaderValue,
         request_headers_header: &HeaderValue,
     ) -> Result<HeaderMap, Error> {
+        if self.is_insecure(origin) {
+            return Err(Error::InsecureOrigin);
+        }
+
         if !self.inner.allowed_origins.is_allowed(origin) {
             return Err(Error::OriginNotAllowed);
         }
 
-        if !is_method_allowed(req_headers.get(REQUEST_METHOD)) {
+        if !self.is_method_allowed(req_headers.get(REQUEST_METHOD)) {
             return Err(Error::MethodNotAllowed);
         }
 
+        if has_forbidden_header(request_headers_header) {
+            return Err(Error::ForbiddenRequestHeader);
+        }
+
         let mut headers = self.common_headers(origin.clone());
-        headers.insert(ALLOW_METHODS, HeaderValue::from_static("POST,OPTIONS"));
+        headers.insert(ALLOW_METHODS, self.allow_methods());
         headers.insert(ALLOW_HEADERS, request_headers_header.clone());
 
-        if let Some(max_age) = self.inner.max_age {
+        let max_age = self.inner.max_age_per_origin.get(origin).copied();
+        if let Some(max_age) = max_age.or(self.inner.max_age) {
+            #[cfg(feature = "jitter")]
+            let max_age = max_age + jitter(self.inner.max_age_jitter);
+
             headers.insert(MAX_AGE, HeaderValue::from(max_age.as_secs()));
         }
 
         Ok(headers)
     }
 
-    fn common_headers(&self, origin: HeaderValue) -> HeaderMap {
+    /// Returns the headers describing what preflights may request, without
+    /// `access-control-allow-origin`, when `debug_cors_errors` is enabled.
+    pub(crate) fn rejected_preflight_headers(&self) -> Option<HeaderMap> {
+        if !self.inner.debug_cors_errors {
+            return None;
+        }
+
         let mut headers = HeaderMap::new();
+        headers.insert(ALLOW_METHODS, self.allow_methods());
+        Some(headers)
+    }
+
+    fn allow_methods(&self) -> HeaderValue {
+        HeaderValue::from_static(if self.inner.allow_patch {
+            "POST,PATCH,OPTIONS"
+        } else {
+            "POST,OPTIONS"
+        })
+    }
+
+    fn common_headers(&self, origin: HeaderValue) -> HeaderMap {
+        let mut headers = HeaderMap::clone(&self.common_headers);
         headers.insert(ALLOW_ORIGIN, origin);
-        headers.insert(
-            EXPOSE_HEADERS,
-            join_header_value(&self.inner.exposed_headers).unwrap(),
-        );
+        headers
+    }
+
+    fn is_insecure(&self, origin: &HeaderValue) -> bool {
+        self.inner.require_tls && !origin.as_bytes().starts_with(b"https://")
+    }
+
+    pub(crate) fn exposed_headers(&self) -> Option<&HeaderValue> {
+        self.common_headers.get(EXPOSE_HEADERS)
+    }
+
+    pub(crate) fn allows_origin(&self, origin: &HeaderValue) -> bool {
+        self.inner.allowed_origins.is_allowed(origin)
+    }
+
+    pub(crate) fn skips_same_origin(&self, uri: &Uri, headers: &HeaderMap) -> bool {
+        self.inner.skip_same_origin_cors && is_same_origin(uri, headers)
+    }
+
+    pub(crate) fn passthrough_non_grpc_web(&self) -> bool {
+        self.inner.passthrough_non_grpc_web
+    }
+
+    pub(crate) fn is_health_check(&self, method: &Method, path: &str) -> bool {
+        method == Method::GET && self.inner.health_check_path.as_deref() == Some(path)
+    }
 
-        if self.inner.allow_credentials {
-            headers.insert(ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+    pub(crate) fn latency_header(&self) -> Option<&HeaderName> {
+        self.inner
+            .debug_latency_header
+            .then_some(&self.inner.latency_header_name)
+    }
+
+    pub(crate) fn request_timeout(&self) -> Option<Duration> {
+        self.inner.request_timeout
+    }
+
+    pub(crate) fn preflight_status(&self) -> StatusCode {
+        self.inner.preflight_status
+    }
+
+    pub(crate) fn trailer_observer(&self) -> Option<&Callback<TrailerObserver>> {
+        self.inner.trailer_observer.as_ref()
+    }
+
+    pub(crate) fn unrecognized_method_status(&self, method: &Method) -> StatusCode {
+        match &self.inner.method_fallback {
+            Some(fallback) => fallback(method.clone()),
+            None => StatusCode::METHOD_NOT_ALLOWED,
         }
+    }
 
-        headers
+    pub(crate) fn allows_inflight_method(&self, method: &Method) -> bool {
+        method == Method::POST || (self.inner.allow_patch && method == Method::PATCH)
     }
-}
 
-fn is_method_allowed(header: Option<&HeaderValue>) -> bool {
-    if let Some(value) = header {
-        if let Ok(method) = Method::from_bytes(value.as_bytes()) {
-            DEFAULT_ALLOWED_METHODS.contains(&method)
-        } else {
-            debug!("access-control-request-method {:?} is not valid", value);
-            false
+    fn is_method_allowed(&self, header: Option<&HeaderValue>) -> bool {
+        let Err(e) = self.check_request_method(header) else {
+            return true;
+        };
+
+        match e {
+            RequestMethodError::Missing => debug!("access-control-request-method is missing"),
+            RequestMethodError::Empty => debug!("access-control-request-method is empty"),
+            RequestMethodError::Invalid => {
+                debug!("access-control-request-method {:?} is not valid", header)
+            }
+            RequestMethodError::NotAllowed => {
+                debug!("access-control-request-method {:?} is not allowed", header)
+            }
         }
-    } else {
-        debug!("access-control-request-method is missing");
         false
     }
+
+    fn check_request_method(&self, header: Option<&HeaderValue>) -> Result<(), RequestMethodError> {
+        let value = header.ok_or(RequestMethodError::Missing)?;
+        let method = value
+            .to_str()
+            .map_err(|_| RequestMethodError::Invalid)?
+            .trim();
+
+        if method.is_empty() {
+            return Err(RequestMethodError::Empty);
+        }
+
+        match Method::from_bytes(method.as_bytes()) {
+            Ok(method)
+                if DEFAULT_ALLOWED_METHODS.contains(&method)
+                    || (self.inner.allow_patch && method == Method::PATCH) =>
+            {
+                Ok(())
+            }
+            Ok(_) => Err(RequestMethodError::NotAllowed),
+            Err(_) => Err(RequestMethodError::Invalid),
+        }
+    }
+}
+
+/// Why an `access-control-request-method` header was rejected.
+#[derive(Debug, PartialEq)]
+enum RequestMethodError {
+    Missing,
+    Empty,
+    Invalid,
+    NotAllowed,
+}
+
+#[cfg(feature = "jitter")]
+fn jitter(max: Duration) -> Duration {
+    use rand::Rng;
+
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+
+    Duration::from_secs(rand::thread_rng().gen_range(0..=max.as_secs()))
+}
+
+/// Compares the scheme, host and port of the `origin` of a request with those it was sent to.
+fn is_same_origin(uri: &Uri, headers: &HeaderMap) -> bool {
+    let scheme = uri
+        .scheme_str()
+        .or_else(|| {
+            let proto = headers.get(X_FORWARDED_PROTO)?.to_str().ok()?;
+            proto.split(',').next()
+        })
+        .map(|scheme| scheme.trim().to_ascii_lowercase());
+    let origin = headers
+        .get(header::ORIGIN)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|o| o.split_once("://"));
+    let host = uri
+        .authority()
+        .cloned()
+        .or_else(|| headers.get(header::HOST)?.to_str().ok()?.parse().ok());
+
+    let (Some(scheme), Some((origin_scheme, origin)), Some(host)) = (scheme, origin, host) else {
+        return false;
+    };
+    let Ok(origin) = origin.parse::<Authority>() else {
+        return false;
+    };
+
+    origin_scheme.eq_ignore_ascii_case(&scheme)
+        && origin.host().eq_ignore_ascii_case(host.host())
+        && port_or_default(&origin, &scheme) == port_or_default(&host, &scheme)
+}
+
+fn port_or_default(authority: &Authority, scheme: &str) -> Option<u16> {
+    authority.port_u16().or(match scheme {
+        "http" => Some(80),
+        "https" => Some(443),
+        _ => None,
+    })
+}
+
+fn has_forbidden_header(value: &HeaderValue) -> bool {
+    let Ok(value) = value.to_str() else {
+        debug!("access-control-request-headers {:?} is not valid", value);
+        return true;
+    };
+
+    value
+        .split(',')
+        .map(|name| name.trim().to_ascii_lowercase())
+        .any(|name| {
+            let forbidden = FORBIDDEN_REQUEST_HEADERS.contains(&name.as_str())
+                || FORBIDDEN_REQUEST_HEADER_PREFIXES
+                    .iter()
+                    .any(|prefix| name.starts_with(prefix));
+
+            if forbidden {
+                debug!("access-control-request-headers contains forbidden header {name}");
+            }
+            forbidden
+        })
+}
+
+/// Checks that `origin` is a scheme followed by an authority, without any path.
+pub(crate) fn parse_origin(origin: HeaderValue) -> Result<HeaderValue, InvalidOriginError> {
+    let well_formed = origin
+        .to_str()
+        .ok()
+        .and_then(|origin| origin.split_once("://"))
+        .map_or(false, |(scheme, authority)| {
+            !scheme.is_empty()
+                && scheme
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"+-.".contains(&b))
+                && !authority.contains(['/', '?', '#', '@'])
+                && authority.parse::<Authority>().is_ok()
+        });
+
+    if well_formed {
+        Ok(origin)
+    } else {
+        Err(InvalidOriginError::Malformed(origin))
+    }
+}
+
+/// Compiles `pattern` so that it only matches whole origins: a bare `example\.com` must not allow
+/// `https://example.com.evil.net`.
+#[cfg(feature = "regex")]
+fn anchored_regex(pattern: &str) -> Result<regex::Regex, regex::Error> {
+    regex::Regex::new(&format!("^(?:{pattern})$"))
+}
+
+fn env_var(name: &'static str) -> Result<Option<String>, ConfigFromEnvError> {
+    match std::env::var(name) {
+        Ok(value) => Ok(Some(value)),
+        Err(VarError::NotPresent) => Ok(None),
+        Err(VarError::NotUnicode(_)) => Err(ConfigFromEnvError::NotUnicode(name)),
+    }
+}
+
+fn split_list(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
 }
 
 fn join_header_value<I>(values: I) -> Result<HeaderValue, header::InvalidHeaderValue>
@@ -218,3 +1113,445 @@ where
     }
     HeaderValue::from_bytes(&value)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+
+    use super::*;
+
+    const ENV_VARS: [&str; 4] = [
+        "GRPC_WEB_ALLOWED_ORIGINS",
+        "GRPC_WEB_EXPOSED_HEADERS",
+        "GRPC_WEB_MAX_AGE_SECONDS",
+        "GRPC_WEB_ALLOW_CREDENTIALS",
+    ];
+
+    /// Sets the `GRPC_WEB_*` variables, and only those given, until dropped. Tests holding it run
+    /// one at a time, as the environment is shared by the whole process.
+    struct EnvGuard {
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl EnvGuard {
+        fn set(vars: &[(&str, &str)]) -> Self {
+            static LOCK: Mutex<()> = Mutex::new(());
+
+            let guard = EnvGuard {
+                _lock: LOCK.lock().unwrap_or_else(PoisonError::into_inner),
+            };
+            for name in ENV_VARS {
+                std::env::remove_var(name);
+            }
+            for (name, value) in vars {
+                std::env::set_var(name, value);
+            }
+            guard
+        }
+    }
+
+    impl Drop for EnvGuard {
+        fn drop(&mut self) {
+            for name in ENV_VARS {
+                std::env::remove_var(name);
+            }
+        }
+    }
+
+    fn from_env_with(vars: &[(&str, &str)]) -> Result<Config, ConfigFromEnvError> {
+        let _env = EnvGuard::set(vars);
+        Config::from_env()
+    }
+
+    #[test]
+    fn expose_headers_dedup_case_insensitively() {
+        let config =
+            Config::new().expose_headers(["grpc-status", "GRPC-STATUS", "x-Custom", "X-CUSTOM"]);
+
+        assert_eq!(
+            config.exposed_headers.len(),
+            DEFAULT_EXPOSED_HEADERS.len() + 1
+        );
+        assert!(config
+            .exposed_headers
+            .contains(&HeaderName::from_static("x-custom")));
+    }
+
+    #[test]
+    fn clear_exposed_headers() {
+        let cors = Cors::new(Config::new().clear_exposed_headers());
+        let headers = cors.common_headers(HeaderValue::from_static("https://example.com"));
+
+        assert!(!headers.contains_key(EXPOSE_HEADERS));
+    }
+
+    #[test]
+    fn timing_allow_origin() {
+        let mut req_headers = HeaderMap::new();
+        req_headers.insert(
+            header::ORIGIN,
+            HeaderValue::from_static("https://example.com"),
+        );
+
+        let cors = Cors::new(Config::new());
+        let headers = cors.simple(&req_headers).unwrap();
+        assert!(!headers.contains_key(TIMING_ALLOW_ORIGIN));
+
+        let cors = Cors::new(Config::new().timing_allow_origin(true));
+        let headers = cors.simple(&req_headers).unwrap();
+        assert_eq!(headers[TIMING_ALLOW_ORIGIN], "https://example.com");
+    }
+
+    #[test]
+    fn duplicate_origins() {
+        let mut req_headers = HeaderMap::new();
+        req_headers.append(
+            header::ORIGIN,
+            HeaderValue::from_static("https://example.com"),
+        );
+        req_headers.append(header::ORIGIN, HeaderValue::from_static("https://evil.com"));
+
+        let cors = Cors::new(
+            Config::new()
+                .allow_origins(["https://example.com"])
+                .unwrap(),
+        );
+        let headers = cors.simple(&req_headers).unwrap();
+        assert_eq!(headers[ALLOW_ORIGIN], "https://example.com");
+
+        let cors = Cors::new(Config::new().allow_origins(["https://evil.com"]).unwrap());
+        assert_eq!(cors.simple(&req_headers), Err(Error::OriginNotAllowed));
+    }
+
+    #[test]
+    fn allow_patterns() {
+        let cors = Cors::new(Config::new().allow_patterns(vec![
+            Box::new(ExactOrigin(HeaderValue::from_static("https://example.com"))),
+            Box::new(PrefixOrigin("http://localhost:".into())),
+            Box::new(GlobOrigin("https://*.example.org".into())),
+        ]));
+
+        for origin in [
+            "https://example.com",
+            "http://localhost:3000",
+            "https://app.example.org",
+        ] {
+            assert!(
+                cors.allows_origin(&HeaderValue::from_static(origin)),
+                "{origin}"
+            );
+        }
+
+        for origin in [
+            "https://example.org",
+            "https://evil.com",
+            "http://localhost",
+        ] {
+            assert!(
+                !cors.allows_origin(&HeaderValue::from_static(origin)),
+                "{origin}"
+            );
+        }
+    }
+
+    #[test]
+    fn same_origin() {
+        let is_same = |uri: &str, headers: &[(&'static str, &'static str)]| {
+            let mut map = HeaderMap::new();
+            for (name, value) in headers {
+                map.insert(*name, HeaderValue::from_static(value));
+            }
+            is_same_origin(&uri.parse().unwrap(), &map)
+        };
+
+        assert!(is_same(
+            "https://api.example.com/svc",
+            &[("origin", "https://api.example.com")]
+        ));
+        assert!(is_same(
+            "/svc",
+            &[
+                ("origin", "https://API.example.com:443"),
+                ("host", "api.example.com"),
+                (X_FORWARDED_PROTO, "https"),
+            ]
+        ));
+        assert!(is_same(
+            "/svc",
+            &[
+                ("origin", "http://api.example.com"),
+                ("host", "api.example.com:80"),
+                (X_FORWARDED_PROTO, "http"),
+            ]
+        ));
+
+        // Another scheme, another port or an unknown scheme.
+        assert!(!is_same(
+            "/svc",
+            &[
+                ("origin", "http://api.example.com"),
+                ("host", "api.example.com"),
+                (X_FORWARDED_PROTO, "https"),
+            ]
+        ));
+        assert!(!is_same(
+            "https://api.example.com/svc",
+            &[("origin", "https://api.example.com:8443")]
+        ));
+        assert!(!is_same(
+            "/svc",
+            &[
+                ("origin", "http://api.example.com"),
+                ("host", "api.example.com"),
+            ]
+        ));
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn allow_origins_regex_is_anchored() {
+        let regex = Cors::new(
+            Config::new()
+                .allow_origins_regex(r"https://(app|admin)\.example\.com")
+                .unwrap(),
+        );
+        let matcher = Cors::new(Config::new().allow_patterns(vec![Box::new(
+            RegexOrigin::new(r"https://(app|admin)\.example\.com").unwrap(),
+        )]));
+
+        for cors in [regex, matcher] {
+            assert!(cors.allows_origin(&HeaderValue::from_static("https://app.example.com")));
+            for origin in [
+                "https://app.example.com.evil.net",
+                "https://evil.net/https://app.example.com",
+                "http://https://admin.example.com",
+            ] {
+                assert!(
+                    !cors.allows_origin(&HeaderValue::from_static(origin)),
+                    "{origin}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn from_env() {
+        assert_eq!(
+            from_env_with(&[]).unwrap().to_string(),
+            Config::new().to_string()
+        );
+
+        let config = from_env_with(&[
+            (
+                "GRPC_WEB_ALLOWED_ORIGINS",
+                "https://a.example.com, https://b.example.com",
+            ),
+            ("GRPC_WEB_EXPOSED_HEADERS", "x-custom"),
+            ("GRPC_WEB_MAX_AGE_SECONDS", " 60 "),
+            ("GRPC_WEB_ALLOW_CREDENTIALS", "true"),
+        ])
+        .unwrap();
+        assert!(config.allow_credentials);
+        assert_eq!(
+            config.to_string(),
+            "cors=[https://a.example.com, https://b.example.com], expose=[grpc-message, \
+             grpc-status, grpc-status-details-bin, x-custom], max_age=60s"
+        );
+        assert!(Cors::try_new(config).is_ok());
+    }
+
+    #[test]
+    fn from_env_errors() {
+        assert!(matches!(
+            from_env_with(&[("GRPC_WEB_ALLOWED_ORIGINS", "example.com")]),
+            Err(ConfigFromEnvError::AllowedOrigins(_))
+        ));
+
+        assert!(matches!(
+            from_env_with(&[("GRPC_WEB_EXPOSED_HEADERS", "bad header")]),
+            Err(ConfigFromEnvError::ExposedHeaders(_))
+        ));
+
+        assert!(matches!(
+            from_env_with(&[("GRPC_WEB_MAX_AGE_SECONDS", "1d")]),
+            Err(ConfigFromEnvError::MaxAge(_))
+        ));
+
+        assert!(matches!(
+            from_env_with(&[("GRPC_WEB_ALLOW_CREDENTIALS", "yes")]),
+            Err(ConfigFromEnvError::AllowCredentials(_))
+        ));
+
+        assert!(matches!(
+            from_env_with(&[("GRPC_WEB_ALLOW_CREDENTIALS", "true")]),
+            Err(ConfigFromEnvError::CredentialsWithoutOrigins)
+        ));
+    }
+
+    #[test]
+    fn require_tls() {
+        let cors = Cors::new(Config::new().require_tls(true));
+        let mut req_headers = HeaderMap::new();
+        req_headers.insert(REQUEST_METHOD, HeaderValue::from_static("POST"));
+        let request_headers = HeaderValue::from_static("x-grpc-web");
+
+        let http = HeaderValue::from_static("http://example.com");
+        req_headers.insert(header::ORIGIN, http.clone());
+        assert_eq!(cors.simple(&req_headers), Err(Error::InsecureOrigin));
+        assert_eq!(
+            cors.preflight(&req_headers, &http, &request_headers),
+            Err(Error::InsecureOrigin)
+        );
+
+        let https = HeaderValue::from_static("https://example.com");
+        req_headers.insert(header::ORIGIN, https.clone());
+        let headers = cors.simple(&req_headers).unwrap();
+        assert_eq!(
+            headers[header::STRICT_TRANSPORT_SECURITY],
+            "max-age=31536000; includeSubDomains"
+        );
+        let headers = cors
+            .preflight(&req_headers, &https, &request_headers)
+            .unwrap();
+        assert!(headers.contains_key(header::STRICT_TRANSPORT_SECURITY));
+
+        let cors = Cors::new(Config::new());
+        req_headers.insert(header::ORIGIN, http.clone());
+        let headers = cors.simple(&req_headers).unwrap();
+        assert!(!headers.contains_key(header::STRICT_TRANSPORT_SECURITY));
+        let headers = cors
+            .preflight(&req_headers, &http, &request_headers)
+            .unwrap();
+        assert!(!headers.contains_key(header::STRICT_TRANSPORT_SECURITY));
+    }
+
+    #[cfg(feature = "jitter")]
+    #[test]
+    fn max_age_jitter() {
+        let cors = Cors::new(
+            Config::new().max_age_jitter(Duration::from_secs(60), Duration::from_secs(10)),
+        );
+        let mut req_headers = HeaderMap::new();
+        req_headers.insert(REQUEST_METHOD, HeaderValue::from_static("POST"));
+        let origin = HeaderValue::from_static("https://example.com");
+        let request_headers = HeaderValue::from_static("x-grpc-web");
+
+        for _ in 0..100 {
+            let headers = cors
+                .preflight(&req_headers, &origin, &request_headers)
+                .unwrap();
+            let max_age: u64 = headers[MAX_AGE].to_str().unwrap().parse().unwrap();
+            assert!((60..=70).contains(&max_age), "{max_age}");
+        }
+    }
+
+    #[test]
+    fn check_request_method() {
+        let cors = Cors::new(Config::new());
+        let check =
+            |value: &'static str| cors.check_request_method(Some(&HeaderValue::from_static(value)));
+
+        assert_eq!(check("POST"), Ok(()));
+        assert_eq!(check(" POST "), Ok(()));
+        assert_eq!(check(""), Err(RequestMethodError::Empty));
+        assert_eq!(check("   "), Err(RequestMethodError::Empty));
+        assert_eq!(check("PO ST"), Err(RequestMethodError::Invalid));
+        assert_eq!(check("GET"), Err(RequestMethodError::NotAllowed));
+        assert_eq!(
+            cors.check_request_method(None),
+            Err(RequestMethodError::Missing)
+        );
+    }
+
+    #[test]
+    fn preflight_request_method() {
+        let cors = Cors::new(Config::new());
+        let origin = HeaderValue::from_static("https://example.com");
+        let request_headers = HeaderValue::from_static("x-grpc-web");
+
+        for method in ["", " ", "PO ST", "GET"] {
+            let mut req_headers = HeaderMap::new();
+            req_headers.insert(REQUEST_METHOD, HeaderValue::from_static(method));
+            assert_eq!(
+                cors.preflight(&req_headers, &origin, &request_headers),
+                Err(Error::MethodNotAllowed),
+                "{method:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn display() {
+        let config = Config::new()
+            .allow_origins(["https://b.example.com", "https://a.example.com"])
+            .unwrap()
+            .max_age(None::<Duration>);
+
+        assert_eq!(
+            config.to_string(),
+            "cors=[https://a.example.com, https://b.example.com], expose=[grpc-message, \
+             grpc-status, grpc-status-details-bin], max_age=none"
+        );
+        assert_eq!(
+            Cors::new(Config::new()).to_string(),
+            "cors=any, expose=[grpc-message, grpc-status, grpc-status-details-bin], max_age=86400s"
+        );
+    }
+
+    #[test]
+    fn allow_origins_invalid() {
+        let res = Config::new().allow_origins(["not a valid\0header"]);
+
+        assert!(matches!(
+            res,
+            Err(InvalidOriginError::InvalidHeaderValue(_))
+        ));
+    }
+
+    #[test]
+    fn max_age_per_origin() {
+        let config = Config::new()
+            .allow_origins(["https://example.com", "https://other.com"])
+            .unwrap()
+            .max_age(Duration::from_secs(60))
+            .max_age_per_origin([("https://example.com", Duration::from_secs(10))])
+            .unwrap();
+        let cors = Cors::new(config);
+        let mut req_headers = HeaderMap::new();
+        req_headers.insert(REQUEST_METHOD, HeaderValue::from_static("POST"));
+        let request_headers = HeaderValue::from_static("x-grpc-web");
+
+        for (origin, max_age) in [("https://example.com", "10"), ("https://other.com", "60")] {
+            let origin = HeaderValue::from_static(origin);
+            let headers = cors
+                .preflight(&req_headers, &origin, &request_headers)
+                .unwrap();
+            assert_eq!(headers[MAX_AGE], max_age);
+        }
+
+        assert!(matches!(
+            Config::new().max_age_per_origin([("example.com", Duration::from_secs(10))]),
+            Err(InvalidOriginError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn allow_origins_malformed() {
+        for origin in [
+            "not-an-origin",
+            "https://",
+            "https://example.com/path",
+            "://example.com",
+        ] {
+            let res = Config::new().allow_origins([origin]);
+            assert!(
+                matches!(res, Err(InvalidOriginError::Malformed(_))),
+                "{origin}"
+            );
+        }
+
+        assert!(Config::new()
+            .allow_origins(["https://api.example.com:8080"])
+            .is_ok());
+    }
+}
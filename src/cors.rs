@@ -0,0 +1,139 @@
+//! A CORS configuration builder reporting every problem at once.
+
+use std::time::Duration;
+
+use http::{header::HeaderName, HeaderValue};
+
+use crate::{config::parse_origin, Config, ConfigError, InvalidOriginError};
+
+/// Accumulates CORS options, validated together by [`Builder::build`].
+#[derive(Clone, Debug, Default)]
+pub struct Builder {
+    allowed_origins: Option<Vec<String>>,
+    exposed_headers: Vec<String>,
+    max_age: Option<Option<Duration>>,
+    allow_credentials: bool,
+    allow_patch: bool,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn allow_origins<I>(self, origins: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        Self {
+            allowed_origins: Some(origins.into_iter().map(Into::into).collect()),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn expose_headers<I>(mut self, headers: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.exposed_headers
+            .extend(headers.into_iter().map(Into::into));
+        self
+    }
+
+    #[must_use]
+    pub fn max_age<T: Into<Option<Duration>>>(self, max_age: T) -> Self {
+        Self {
+            max_age: Some(max_age.into()),
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn allow_credentials(self, allow_credentials: bool) -> Self {
+        Self {
+            allow_credentials,
+            ..self
+        }
+    }
+
+    #[must_use]
+    pub fn allow_patch(self, allow_patch: bool) -> Self {
+        Self {
+            allow_patch,
+            ..self
+        }
+    }
+
+    /// Builds the [`Config`], or returns every problem found in the options.
+    pub fn build(self) -> Result<Config, Vec<ConfigError>> {
+        let mut errors = Vec::new();
+
+        let origins = self.allowed_origins.map(|origins| {
+            origins
+                .iter()
+                .filter_map(|origin| {
+                    HeaderValue::try_from(origin.as_str())
+                        .map_err(InvalidOriginError::from)
+                        .and_then(parse_origin)
+                        .map_err(|e| errors.push(ConfigError::InvalidOrigin(e)))
+                        .ok()
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let exposed_headers: Vec<_> = self
+            .exposed_headers
+            .iter()
+            .filter_map(|header| {
+                HeaderName::try_from(header.as_str())
+                    .map_err(|e| errors.push(ConfigError::InvalidHeaderName(e)))
+                    .ok()
+            })
+            .collect();
+
+        if self.allow_credentials && origins.is_none() {
+            errors.push(ConfigError::CredentialsWithWildcard);
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut config = Config::new()
+            .expose_headers(exposed_headers)
+            .allow_credentials(self.allow_credentials)
+            .allow_patch(self.allow_patch);
+
+        if let Some(origins) = origins {
+            config = config.allow_origins_unchecked(origins);
+        }
+
+        if let Some(max_age) = self.max_age {
+            config = config.max_age(max_age);
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_every_error() {
+        let errors = Builder::new()
+            .expose_headers(["bad header"])
+            .allow_credentials(true)
+            .build()
+            .unwrap_err();
+
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(errors[0], ConfigError::InvalidHeaderName(_)));
+        assert!(matches!(errors[1], ConfigError::CredentialsWithWildcard));
+    }
+}
@@ -0,0 +1,30 @@
+//! Type-level directions of a [`WebCall`](crate::WebCall).
+
+/// A grpc-web request body, decoded into a gRPC one.
+#[derive(Debug)]
+pub enum Request {}
+
+/// A gRPC response body, encoded into a grpc-web one.
+#[derive(Debug)]
+pub enum Response {}
+
+/// The direction of a [`WebCall`](crate::WebCall), either [`Request`] or [`Response`].
+pub trait Direction: sealed::Sealed {}
+
+impl Direction for Request {}
+
+impl Direction for Response {}
+
+mod sealed {
+    pub trait Sealed {
+        const IS_REQUEST: bool;
+    }
+
+    impl Sealed for super::Request {
+        const IS_REQUEST: bool = true;
+    }
+
+    impl Sealed for super::Response {
+        const IS_REQUEST: bool = false;
+    }
+}
@@ -23,7 +23,9 @@
 //!
 //!     Server::new()
 //!         .accept_http1(true)
-//!         .layer_outer(WebLayer::new(Cors::new(Config::default())))
+//!         .layer_outer(WebLayer::new(Cors::new(
+//!             Config::default().allow_origins(["https://example.com"]).unwrap(),
+//!         )))
 //!         .add_service(ServiceBuilder::new(GreeterServer::new(S)).build())
 //!         .run(address)
 //!         .await
@@ -38,36 +40,172 @@
 #![feature(impl_trait_in_assoc_type)]
 
 mod codec;
+#[cfg(feature = "compression")]
+mod compression;
 mod config;
+pub mod cors;
+pub mod direction;
+#[cfg(feature = "middleware")]
+pub mod middleware;
+pub mod proto;
+#[cfg(feature = "test-utils")]
+pub mod test_utils;
 
-use std::future::Future;
+use std::{fmt, future::Future, sync::Arc, time::Instant};
 
-use codec::{Encoding, WebCall};
-pub use config::{Config, Cors};
+use base64::{engine::general_purpose, Engine};
+use bytes::Bytes;
+use codec::{encode_trailers, internal_error};
+pub use codec::{Encoding, WebCall};
+#[cfg(feature = "regex")]
+pub use config::RegexOrigin;
+pub use config::{
+    Config, ConfigError, ConfigFromEnvError, Cors, ExactOrigin, GlobOrigin, InvalidOriginError,
+    OriginMatcher, PrefixOrigin,
+};
 use http::{
     header::{self, CONTENT_TYPE, ORIGIN},
     HeaderMap, Response, StatusCode, Version,
 };
 use hyper::{http::HeaderValue, Method};
-use tracing::{debug, trace};
-use volo::{Layer, Service};
-use volo_grpc::{body::Body, context::ServerContext, server::NamedService, Status};
+use tracing::{debug, trace, warn};
+use volo::{net::Address, Layer, Service};
+use volo_grpc::{body::Body, context::ServerContext, server::NamedService, Code, Status};
+
+use crate::config::{Callback, TrailerObserver, REQUEST_HEADERS};
 
-use crate::config::REQUEST_HEADERS;
+pub(crate) const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
 
 pub(crate) const GRPC_WEB: &str = "application/grpc-web";
 pub(crate) const GRPC_WEB_PROTO: &str = "application/grpc-web+proto";
 pub(crate) const GRPC_WEB_TEXT: &str = "application/grpc-web-text";
 pub(crate) const GRPC_WEB_TEXT_PROTO: &str = "application/grpc-web-text+proto";
+// Forwarded as `application/grpc+json`, so only inner services decoding JSON accept it.
+pub(crate) const GRPC_WEB_TEXT_JSON: &str = "application/grpc-web-text+json";
+
+const GRPC: &str = "application/grpc";
+const GRPC_JSON: &str = "application/grpc+json";
+
+// The content types advertised when rejecting an unknown grpc-web subformat.
+const SUPPORTED_CONTENT_TYPES: [&str; 5] = [
+    GRPC_WEB,
+    GRPC_WEB_PROTO,
+    GRPC_WEB_TEXT,
+    GRPC_WEB_TEXT_PROTO,
+    GRPC_WEB_TEXT_JSON,
+];
 
+/// A [`Layer`] that lets a volo-grpc server handle grpc-web requests.
+///
+/// Browsers send grpc-web requests over HTTP/1.1, so the server must be built with
+/// `accept_http1(true)`. Otherwise those requests are rejected before they reach this layer,
+/// which therefore cannot detect nor report the misconfiguration.
 #[derive(Clone, Debug)]
 pub struct WebLayer {
     cors: Cors,
+    origin_routes: Arc<[Cors]>,
+    hooks: Hooks,
+    dry_run: bool,
 }
 
 impl WebLayer {
     pub fn new(cors: Cors) -> Self {
-        Self { cors }
+        Self {
+            cors,
+            origin_routes: Arc::new([]),
+            hooks: Hooks::default(),
+            dry_run: false,
+        }
+    }
+
+    /// Handles each request with the CORS policy of the first of `configs` allowing its origin.
+    /// Requests without an origin, or from any other origin, use the policy of `fallback`: to
+    /// reject them, give it no allowed origins.
+    ///
+    /// Only the CORS policy is routed by origin. Every other setting, such as the request timeout
+    /// or the accepted methods, is taken from `fallback` for all requests.
+    pub fn multi_cors(configs: Vec<Cors>, fallback: Cors) -> Self {
+        Self {
+            origin_routes: configs.into(),
+            ..Self::new(fallback)
+        }
+    }
+
+    /// Returns the layer with its CORS configuration replaced by `cors`.
+    #[must_use]
+    pub fn with_cors_config(mut self, cors: Cors) -> Self {
+        self.cors = cors;
+        self
+    }
+
+    /// Forwards requests matching `predicate` to the inner service untouched, without any
+    /// grpc-web translation nor CORS handling.
+    #[must_use]
+    pub fn skip_if<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&http::Request<hyper::Body>) -> bool + Send + Sync + 'static,
+    {
+        self.hooks.skip = Some(Callback(Arc::new(predicate)));
+        self
+    }
+
+    /// Calls `hook` on every grpc-web request, after it has been translated to gRPC and before it
+    /// is forwarded to the inner service.
+    #[must_use]
+    pub fn with_request_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(&mut http::Request<hyper::Body>) + Send + Sync + 'static,
+    {
+        self.hooks.request = Some(Callback(Arc::new(hook)));
+        self
+    }
+
+    /// Calls `hook` on every successful grpc-web response, after it has been translated from
+    /// gRPC. Rejections and error responses are not passed to the hook.
+    #[must_use]
+    pub fn with_response_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(Response<Body>) -> Response<Body> + Send + Sync + 'static,
+    {
+        self.hooks.response = Some(Callback(Arc::new(hook)));
+        self
+    }
+
+    /// Decodes grpc-web requests but answers them with an empty `grpc-status: 0` response instead
+    /// of calling the inner service, to measure the overhead of this layer in load tests.
+    #[must_use]
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+}
+
+type SkipPredicate = dyn Fn(&http::Request<hyper::Body>) -> bool + Send + Sync;
+type RequestHook = dyn Fn(&mut http::Request<hyper::Body>) + Send + Sync;
+type ResponseHook = dyn Fn(Response<Body>) -> Response<Body> + Send + Sync;
+
+/// User supplied callbacks run by [`WebService`].
+#[derive(Clone, Debug, Default)]
+struct Hooks {
+    skip: Option<Callback<SkipPredicate>>,
+    request: Option<Callback<RequestHook>>,
+    response: Option<Callback<ResponseHook>>,
+}
+
+impl fmt::Display for WebLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.origin_routes.is_empty() {
+            return write!(f, "WebLayer({})", self.cors);
+        }
+
+        f.write_str("WebLayer([")?;
+        for (i, cors) in self.origin_routes.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{cors}")?;
+        }
+        write!(f, "], fallback={})", self.cors)
     }
 }
 
@@ -75,7 +213,16 @@ impl<S> Layer<S> for WebLayer {
     type Service = WebService<S>;
 
     fn layer(self, inner: S) -> Self::Service {
-        WebService::new(inner, self.cors)
+        let origin_routes = self
+            .origin_routes
+            .iter()
+            .map(|cors| cors.with_server_settings(&self.cors))
+            .collect();
+        let mut service = WebService::new(inner, self.cors);
+        service.origin_routes = origin_routes;
+        service.hooks = self.hooks;
+        service.dry_run = self.dry_run;
+        service
     }
 }
 
@@ -83,11 +230,40 @@ impl<S> Layer<S> for WebLayer {
 pub struct WebService<S> {
     inner: S,
     cors: Cors,
+    origin_routes: Arc<[Cors]>,
+    hooks: Hooks,
+    dry_run: bool,
 }
 
 impl<S> WebService<S> {
     pub fn new(inner: S, cors: Cors) -> Self {
-        Self { inner, cors }
+        let inner_type = std::any::type_name::<S>();
+        if inner_type.contains(concat!(module_path!(), "::WebService<")) {
+            warn!(
+                inner = inner_type,
+                "grpc-web layer is applied more than once, the inner layer will reject requests \
+                 translated by the outer one"
+            );
+        }
+
+        Self {
+            inner,
+            cors,
+            origin_routes: Arc::new([]),
+            hooks: Hooks::default(),
+            dry_run: false,
+        }
+    }
+
+    fn cors_for(&self, headers: &HeaderMap) -> &Cors {
+        headers
+            .get(ORIGIN)
+            .and_then(|origin| {
+                self.origin_routes
+                    .iter()
+                    .find(|cors| cors.allows_origin(origin))
+            })
+            .unwrap_or(&self.cors)
     }
 }
 
@@ -97,10 +273,12 @@ where
 {
     fn no_content(
         &self,
+        status: StatusCode,
         headers: HeaderMap,
     ) -> impl Future<Output = Result<S::Response, S::Error>> {
         let mut res = Response::builder()
-            .status(StatusCode::NO_CONTENT)
+            .status(status)
+            .header(header::CACHE_CONTROL, "no-store")
             .body(Body::new(Box::pin(futures::stream::empty())))
             .unwrap();
 
@@ -109,15 +287,229 @@ where
         async { Ok(res) }
     }
 
-    fn response(&self, status: StatusCode) -> impl Future<Output = Result<S::Response, S::Error>> {
-        let res = Response::builder()
+    fn response(
+        &self,
+        cors: &Cors,
+        status: StatusCode,
+    ) -> impl Future<Output = Result<S::Response, S::Error>> {
+        let mut res = Response::builder()
             .status(status)
+            .header(header::CACHE_CONTROL, "no-store")
             .body(Body::new(Box::pin(futures::stream::empty())))
             .unwrap();
+
+        if let Some(exposed_headers) = cors.exposed_headers() {
+            res.headers_mut().insert(
+                header::ACCESS_CONTROL_EXPOSE_HEADERS,
+                exposed_headers.clone(),
+            );
+        }
+
         async { Ok(res) }
     }
 }
 
+impl<S> WebService<S>
+where
+    S: Service<ServerContext, http::Request<hyper::Body>, Response = http::Response<Body>>
+        + Send
+        + Sync
+        + 'static,
+    S::Error: Into<Status>,
+{
+    async fn handle(
+        &self,
+        cors: &Cors,
+        cx: &mut ServerContext,
+        req: http::Request<hyper::Body>,
+    ) -> Result<S::Response, S::Error> {
+        // Refuse methods usable for cross-site tracing, whatever the content type.
+        if req.method() == Method::TRACE || req.method().as_str() == "TRACK" {
+            debug!(kind = "trace", method = ?req.method(), "method not implemented");
+            return self.response(cors, StatusCode::NOT_IMPLEMENTED).await;
+        }
+
+        if cors.is_health_check(req.method(), req.uri().path()) {
+            trace!(kind = "health check", peer = ?peer(cx));
+            return Ok(health_response());
+        }
+
+        match RequestKind::new(req.headers(), req.method(), req.version()) {
+            RequestKind::InFlight {
+                method,
+                encoding,
+                accept,
+                content_type,
+                grpc_content_type,
+            } if cors.allows_inflight_method(method) => {
+                // Requests without an origin need no CORS headers. Same origin requests are still
+                // checked, so that skipping their headers never lets a disallowed origin through.
+                let cors_headers = match req.headers().get(ORIGIN) {
+                    Some(origin) => match cors.simple(req.headers()) {
+                        Ok(headers) if cors.skips_same_origin(req.uri(), req.headers()) => {
+                            trace!(kind = "inflight", path = req.uri().path(), "same origin");
+                            // `strict-transport-security` is not a CORS header, keep it.
+                            let mut same_origin = HeaderMap::new();
+                            if let Some(hsts) = headers.get(header::STRICT_TRANSPORT_SECURITY) {
+                                same_origin.insert(header::STRICT_TRANSPORT_SECURITY, hsts.clone());
+                            }
+                            Some(same_origin)
+                        }
+                        Ok(headers) => Some(headers),
+                        Err(e) => {
+                            if cors.first_rejection(origin, &e) {
+                                warn!(kind = "cors_rejection", ?origin, error = ?e);
+                            } else {
+                                debug!(kind = "inflight", path = req.uri().path(), error = ?e);
+                            }
+                            return self.response(cors, StatusCode::FORBIDDEN).await;
+                        }
+                    },
+                    None => {
+                        trace!(kind = "inflight", path = req.uri().path(), "no origin");
+                        None
+                    }
+                };
+
+                trace!(
+                    kind = "inflight",
+                    path = ?req.uri().path(),
+                    rpc = ?cx.rpc_info.method,
+                    peer = ?peer(cx),
+                    ?encoding,
+                    ?accept
+                );
+
+                let mut req = coerce_request(req, encoding, grpc_content_type);
+                if let Some(hook) = &self.hooks.request {
+                    hook(&mut req);
+                }
+
+                let res = if self.dry_run {
+                    // Answer as if the inner service returned no message, once decoded.
+                    Err(match hyper::body::to_bytes(req.into_body()).await {
+                        Ok(_) => Status::new(Code::Ok, ""),
+                        Err(e) => internal_error(e),
+                    })
+                } else {
+                    let fut = self.inner.call(cx, req);
+                    // Bounds the wait for the response headers, not the streaming of the body.
+                    match cors.request_timeout() {
+                        Some(timeout) => match tokio::time::timeout(timeout, fut).await {
+                            Ok(res) => res.map_err(Into::into),
+                            Err(_) => {
+                                debug!(kind = "inflight", ?timeout, "request timed out");
+                                Err(Status::deadline_exceeded("grpc-web: request timed out"))
+                            }
+                        },
+                        None => fut.await.map_err(Into::into),
+                    }
+                };
+
+                let observer = cors.trailer_observer();
+                let mut resp = match res {
+                    Ok(res) => {
+                        let res = coerce_response(res, accept, content_type, observer);
+                        match &self.hooks.response {
+                            Some(hook) => hook(res),
+                            None => res,
+                        }
+                    }
+                    Err(status) => status_response(status, accept, content_type, observer),
+                };
+                if let Some(headers) = cors_headers {
+                    resp.headers_mut().extend(headers);
+                }
+                Ok(resp)
+            }
+
+            RequestKind::InFlight { method, .. } => {
+                debug!(kind = "inflight", error = "method not allowed", ?method);
+                self.response(cors, cors.unrecognized_method_status(method))
+                    .await
+            }
+
+            RequestKind::PreFlight {
+                origin,
+                request_headers,
+            } => match cors.preflight(req.headers(), origin, request_headers) {
+                Ok(headers) => {
+                    trace!(
+                        kind = "preflight",
+                        path = ?cx.rpc_info.method,
+                        peer = ?peer(cx),
+                        ?origin
+                    );
+                    self.no_content(cors.preflight_status(), headers).await
+                }
+                Err(e) => {
+                    if cors.first_rejection(origin, &e) {
+                        warn!(kind = "cors_rejection", ?origin, error = ?e);
+                    } else {
+                        debug!(kind = "preflight", path = req.uri().path(), error = ?e);
+                    }
+                    let mut res = self.response(cors, StatusCode::FORBIDDEN).await?;
+                    if let Some(headers) = cors.rejected_preflight_headers() {
+                        res.headers_mut().extend(headers);
+                    }
+                    Ok(res)
+                }
+            },
+
+            RequestKind::Unsupported => {
+                debug!(
+                    kind = "unsupported",
+                    content_type = ?req.headers().get(header::CONTENT_TYPE),
+                    "rejecting"
+                );
+                let mut res = self
+                    .response(cors, StatusCode::UNSUPPORTED_MEDIA_TYPE)
+                    .await?;
+                let body = format!(
+                    "supported content types: {}",
+                    SUPPORTED_CONTENT_TYPES.join(", ")
+                );
+                *res.body_mut() = Body::new(Box::pin(futures::stream::once(
+                    futures::future::ready(Ok(Bytes::from(body))),
+                )));
+                res.headers_mut()
+                    .insert(header::CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+                Ok(res)
+            }
+
+            RequestKind::Other(Version::HTTP_2) => {
+                debug!(
+                    kind = "other h2",
+                    method = ?req.method(),
+                    content_type = ?req.headers().get(header::CONTENT_TYPE),
+                    "passing through"
+                );
+                self.inner.call(cx, req).await
+            }
+
+            RequestKind::Other(_) if cors.passthrough_non_grpc_web() => {
+                debug!(
+                    kind = "other h1",
+                    method = ?req.method(),
+                    content_type = ?req.headers().get(header::CONTENT_TYPE),
+                    "passing through"
+                );
+                self.inner.call(cx, req).await
+            }
+
+            RequestKind::Other(_) => {
+                debug!(
+                    kind = "other h1",
+                    method = ?req.method(),
+                    content_type = ?req.headers().get(header::CONTENT_TYPE),
+                    "rejecting"
+                );
+                self.response(cors, StatusCode::BAD_REQUEST).await
+            }
+        }
+    }
+}
+
 impl<S> Service<ServerContext, http::Request<hyper::Body>> for WebService<S>
 where
     S: Service<ServerContext, http::Request<hyper::Body>, Response = http::Response<Body>>
@@ -141,70 +533,65 @@ where
         's: 'cx,
     {
         async move {
-            match RequestKind::new(req.headers(), req.method(), req.version()) {
-                RequestKind::InFlight {
-                    method: &Method::POST,
-                    encoding,
-                    accept,
-                } => match self.cors.simple(req.headers()) {
-                    Ok(headers) => {
-                        trace!(kind = "inflight", path = ?req.uri().path(), ?encoding, ?accept);
-
-                        let fut = self.inner.call(cx, coerce_request(req, encoding));
-
-                        let mut resp = coerce_response(fut.await?, accept);
-                        resp.headers_mut().extend(headers);
-                        Ok(resp)
-                    }
-                    Err(e) => {
-                        debug!(kind = "inflight", error=?e, ?req);
-                        self.response(StatusCode::FORBIDDEN).await
-                    }
-                },
-
-                RequestKind::InFlight { .. } => {
-                    debug!(kind = "inflight", error="method not allowed", method = ?req.method());
-                    self.response(StatusCode::METHOD_NOT_ALLOWED).await
+            if let Some(skip) = &self.hooks.skip {
+                if skip(&req) {
+                    trace!(kind = "skipped", path = req.uri().path());
+                    return self.inner.call(cx, req).await;
                 }
+            }
 
-                RequestKind::PreFlight {
-                    origin,
-                    request_headers,
-                } => match self.cors.preflight(req.headers(), origin, request_headers) {
-                    Ok(headers) => {
-                        trace!(kind = "preflight", path = ?cx.rpc_info.method, ?origin);
-                        self.no_content(headers).await
-                    }
-                    Err(e) => {
-                        debug!(kind = "preflight", error = ?e, ?req);
-                        self.response(StatusCode::FORBIDDEN).await
-                    }
-                },
-
-                RequestKind::Other(Version::HTTP_2) => {
-                    debug!(kind = "other h2", content_type = ?req.headers().get(header::CONTENT_TYPE));
-                    self.inner.call(cx, req).await
-                }
+            let start = Instant::now();
+            let cors = self.cors_for(req.headers());
+            let mut res = self.handle(cors, cx, req).await?;
 
-                RequestKind::Other(_) => {
-                    debug!(kind = "other h1", content_type = ?req.headers().get(header::CONTENT_TYPE));
-                    self.response(StatusCode::BAD_REQUEST).await
-                }
+            if let Some(name) = cors.latency_header() {
+                let elapsed = start.elapsed().as_millis() as u64;
+                res.headers_mut()
+                    .insert(name.clone(), HeaderValue::from(elapsed));
             }
+
+            res.headers_mut().insert(
+                header::X_CONTENT_TYPE_OPTIONS,
+                HeaderValue::from_static("nosniff"),
+            );
+
+            Ok(res)
         }
     }
 }
 
+fn health_response() -> http::Response<Body> {
+    let body = futures::stream::once(futures::future::ready(Ok(Bytes::from_static(
+        br#"{"status":"serving"}"#,
+    ))));
+    let mut res = Response::new(Body::new(Box::pin(body)));
+
+    res.headers_mut().insert(
+        header::CONTENT_TYPE,
+        HeaderValue::from_static("application/json"),
+    );
+
+    res
+}
+
+fn peer(cx: &ServerContext) -> Option<&Address> {
+    cx.rpc_info
+        .caller
+        .as_ref()
+        .and_then(|caller| caller.address.as_ref())
+}
+
 fn coerce_request(
     mut req: http::Request<hyper::Body>,
     encoding: Encoding,
+    content_type: &'static str,
 ) -> http::Request<hyper::Body> {
-    req.headers_mut().remove(header::CONTENT_LENGTH);
+    if let Some(len) = req.headers_mut().remove(header::CONTENT_LENGTH) {
+        trace!(kind = "inflight", content_length = ?len, "removed content-length");
+    }
 
-    req.headers_mut().insert(
-        header::CONTENT_TYPE,
-        HeaderValue::from_static("application/grpc"),
-    );
+    req.headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
 
     req.headers_mut()
         .insert(header::TE, HeaderValue::from_static("trailers"));
@@ -214,19 +601,91 @@ fn coerce_request(
         HeaderValue::from_static("identity,deflate,gzip"),
     );
 
+    // Unless already set, e.g. by a proxy, `x-forwarded-proto` is derived from the scheme of the
+    // page that made the request, not of the connection to this server. Any client can set
+    // `origin`, so this is only a hint that services must not rely on for security decisions.
+    let proto = req.headers().get(ORIGIN).and_then(|origin| {
+        let origin = origin.as_bytes();
+        if origin.starts_with(b"https://") {
+            Some("https")
+        } else if origin.starts_with(b"http://") {
+            Some("http")
+        } else {
+            None
+        }
+    });
+    if let Some(proto) = proto {
+        req.headers_mut()
+            .entry(X_FORWARDED_PROTO)
+            .or_insert(HeaderValue::from_static(proto));
+    }
+
     req.map(|b| WebCall::request(b, encoding))
         .map(hyper::Body::wrap_stream)
 }
 
-fn coerce_response(res: http::Response<Body>, encoding: Encoding) -> http::Response<Body> {
+fn coerce_response(
+    res: http::Response<Body>,
+    encoding: Encoding,
+    content_type: &'static str,
+    observer: Option<&Callback<TrailerObserver>>,
+) -> http::Response<Body> {
+    #[cfg(feature = "compression")]
+    let mut res = res;
+    #[cfg(feature = "compression")]
+    let compression = compression::Compression::take(res.headers_mut());
+
     let mut res = res
-        .map(|b| WebCall::response(b, encoding))
+        .map(|b| {
+            let call = WebCall::response(b, encoding);
+            #[cfg(feature = "compression")]
+            let call = call.with_compression(compression);
+
+            match observer.cloned() {
+                Some(observer) => call.with_trailer_observer(move |trailers| observer(trailers)),
+                None => call,
+            }
+        })
         .map(|b| Body::new(Box::pin(b)));
 
-    res.headers_mut().insert(
-        header::CONTENT_TYPE,
-        HeaderValue::from_static(encoding.to_content_type()),
-    );
+    res.headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+
+    res
+}
+
+/// Builds a trailers-only grpc-web response carrying `status`.
+fn status_response(
+    status: Status,
+    encoding: Encoding,
+    content_type: &'static str,
+    observer: Option<&Callback<TrailerObserver>>,
+) -> http::Response<Body> {
+    // `to_header_map` already writes `grpc-status-details-bin`, keep it when falling back too.
+    let trailers = status.to_header_map().unwrap_or_else(|_| {
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from(status.code() as i32));
+        if !status.details().is_empty() {
+            let details = general_purpose::STANDARD_NO_PAD.encode(status.details());
+            trailers.insert(
+                "grpc-status-details-bin",
+                HeaderValue::try_from(details).expect("base64 is a valid header value"),
+            );
+        }
+        trailers
+    });
+
+    if let Some(observer) = observer {
+        observer(&trailers);
+    }
+
+    let frame = encode_trailers(trailers, encoding);
+    let mut res = Response::new(Body::new(Box::pin(futures::stream::once(
+        futures::future::ready(Ok(frame)),
+    ))));
+
+    res.headers_mut()
+        .insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
 
     res
 }
@@ -241,27 +700,59 @@ enum RequestKind<'a> {
         method: &'a Method,
         encoding: Encoding,
         accept: Encoding,
+        content_type: &'static str,
+        /// The content type of the request once translated to gRPC.
+        grpc_content_type: &'static str,
     },
     PreFlight {
         origin: &'a HeaderValue,
         request_headers: &'a HeaderValue,
     },
+    /// A grpc-web content type with a subformat we do not support, such as `+thrift`.
+    Unsupported,
     Other(http::Version),
 }
 
 impl<'a> RequestKind<'a> {
+    // Like every header lookup here, only the first `origin` of a request is used.
     fn new(headers: &'a HeaderMap, method: &'a Method, version: Version) -> Self {
+        let content_type = headers
+            .get(CONTENT_TYPE)
+            .and_then(|val| val.to_str().ok())
+            .map(str::to_ascii_lowercase);
         if matches!(
-            headers.get(CONTENT_TYPE).and_then(|val| val.to_str().ok()),
-            Some(GRPC_WEB | GRPC_WEB_PROTO | GRPC_WEB_TEXT | GRPC_WEB_TEXT_PROTO)
+            content_type.as_deref(),
+            Some(
+                GRPC_WEB
+                    | GRPC_WEB_PROTO
+                    | GRPC_WEB_TEXT
+                    | GRPC_WEB_TEXT_PROTO
+                    | GRPC_WEB_TEXT_JSON
+            )
         ) {
+            let json = content_type.as_deref() == Some(GRPC_WEB_TEXT_JSON);
+            // Without an `accept` header, JSON requests are answered in kind.
+            let (accept, response_content_type) = match headers.get(header::ACCEPT) {
+                None if json => (Encoding::Base64, GRPC_WEB_TEXT_JSON),
+                _ => (
+                    Encoding::from_accept(headers),
+                    Encoding::accept_content_type(headers),
+                ),
+            };
+
             return RequestKind::InFlight {
                 method,
                 encoding: Encoding::from_content_type(headers),
-                accept: Encoding::from_accept(headers),
+                accept,
+                content_type: response_content_type,
+                grpc_content_type: if json { GRPC_JSON } else { GRPC },
             };
         }
 
+        if matches!(&content_type, Some(content_type) if content_type.starts_with(GRPC_WEB)) {
+            return RequestKind::Unsupported;
+        }
+
         if let (&Method::OPTIONS, Some(origin), Some(value)) =
             (method, headers.get(ORIGIN), headers.get(REQUEST_HEADERS))
         {
@@ -279,3 +770,438 @@ impl<'a> RequestKind<'a> {
         RequestKind::Other(version)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+
+    use super::*;
+
+    /// Answers every request with the headers it received, to check what reached it.
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Service<ServerContext, http::Request<hyper::Body>> for Echo {
+        type Response = http::Response<Body>;
+
+        type Error = Status;
+
+        type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+        fn call<'cx, 's>(
+            &'s self,
+            _: &'cx mut ServerContext,
+            req: http::Request<hyper::Body>,
+        ) -> Self::Future<'cx>
+        where
+            's: 'cx,
+        {
+            async move {
+                let mut res = Response::new(Body::new(Box::pin(futures::stream::empty())));
+                *res.headers_mut() = req.headers().clone();
+                Ok(res)
+            }
+        }
+    }
+
+    fn call<S>(service: &WebService<S>, req: http::Request<hyper::Body>) -> http::Response<Body>
+    where
+        S: Service<ServerContext, http::Request<hyper::Body>, Response = http::Response<Body>>
+            + Send
+            + Sync
+            + 'static,
+        S::Error: Into<Status> + fmt::Debug,
+    {
+        block_on(service.call(&mut ServerContext::default(), req)).unwrap()
+    }
+
+    fn grpc_web_request(headers: &[(&'static str, &'static str)]) -> http::Request<hyper::Body> {
+        let mut req = http::Request::builder()
+            .method(Method::POST)
+            .header(CONTENT_TYPE, GRPC_WEB)
+            .body(hyper::Body::empty())
+            .unwrap();
+        for (name, value) in headers {
+            req.headers_mut()
+                .insert(*name, HeaderValue::from_static(value));
+        }
+        req
+    }
+
+    #[test]
+    fn coerce_request_keeps_browser_headers() {
+        let req = http::Request::builder()
+            .header(header::ACCEPT_LANGUAGE, "en-US,en;q=0.9")
+            .header("dnt", "1")
+            .header("x-custom", "value")
+            .body(hyper::Body::empty())
+            .unwrap();
+
+        let req = coerce_request(req, Encoding::None, GRPC);
+
+        assert_eq!(req.headers()[header::ACCEPT_LANGUAGE], "en-US,en;q=0.9");
+        assert_eq!(req.headers()["dnt"], "1");
+        assert_eq!(req.headers()["x-custom"], "value");
+    }
+
+    #[test]
+    fn coerce_request_forwarded_proto() {
+        let request = |headers: &[(&'static str, &'static str)]| {
+            let mut req = http::Request::new(hyper::Body::empty());
+            for (name, value) in headers {
+                req.headers_mut()
+                    .insert(*name, HeaderValue::from_static(value));
+            }
+            coerce_request(req, Encoding::None, GRPC)
+        };
+
+        let req = request(&[("origin", "https://example.com")]);
+        assert_eq!(req.headers()[X_FORWARDED_PROTO], "https");
+
+        let req = request(&[("origin", "http://example.com")]);
+        assert_eq!(req.headers()[X_FORWARDED_PROTO], "http");
+
+        let req = request(&[
+            ("origin", "https://example.com"),
+            (X_FORWARDED_PROTO, "http"),
+        ]);
+        assert_eq!(req.headers()[X_FORWARDED_PROTO], "http");
+
+        let req = request(&[("origin", "null")]);
+        assert!(!req.headers().contains_key(X_FORWARDED_PROTO));
+    }
+
+    #[test]
+    fn multi_cors() {
+        let brand = Config::new()
+            .allow_origins(["https://brand.example.com"])
+            .unwrap()
+            .expose_headers(["x-brand"]);
+        let fallback = Config::new()
+            .allow_origins(["https://example.com"])
+            .unwrap()
+            .allow_patch(true)
+            .health_check_path("/healthz");
+        let service = WebLayer::multi_cors(vec![Cors::new(brand)], Cors::new(fallback)).layer(Echo);
+
+        // Settings other than the CORS policy come from the fallback, whatever the origin.
+        let mut req = grpc_web_request(&[("origin", "https://brand.example.com")]);
+        *req.method_mut() = Method::PATCH;
+        let res = call(&service, req);
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://brand.example.com"
+        );
+        assert!(res.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS]
+            .to_str()
+            .unwrap()
+            .contains("x-brand"));
+
+        let res = call(
+            &service,
+            grpc_web_request(&[("origin", "https://example.com")]),
+        );
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res.headers()[header::ACCESS_CONTROL_EXPOSE_HEADERS]
+            .to_str()
+            .unwrap()
+            .contains("x-brand"));
+
+        let res = call(
+            &service,
+            grpc_web_request(&[("origin", "https://evil.com")]),
+        );
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let res = call(&service, grpc_web_request(&[]));
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        let req = http::Request::builder()
+            .uri("/healthz")
+            .body(hyper::Body::empty())
+            .unwrap();
+        let res = call(&service, req);
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_TYPE], "application/json");
+    }
+
+    #[test]
+    fn skip_same_origin_cors() {
+        let config = Config::new()
+            .allow_origins(["https://api.example.com", "http://dev.example.com"])
+            .unwrap()
+            .skip_same_origin_cors(true);
+        let service = WebService::new(Echo, Cors::new(config));
+
+        let res = call(
+            &service,
+            grpc_web_request(&[
+                ("origin", "https://api.example.com"),
+                ("host", "api.example.com"),
+                (X_FORWARDED_PROTO, "https"),
+            ]),
+        );
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(!res
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+
+        // The scheme differs, so the request is cross origin.
+        let res = call(
+            &service,
+            grpc_web_request(&[
+                ("origin", "http://dev.example.com"),
+                ("host", "dev.example.com"),
+                (X_FORWARDED_PROTO, "https"),
+            ]),
+        );
+        assert_eq!(
+            res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "http://dev.example.com"
+        );
+
+        // Same origin requests are still checked against the allowed origins.
+        let res = call(
+            &service,
+            grpc_web_request(&[
+                ("origin", "https://other.example.com"),
+                ("host", "other.example.com"),
+                (X_FORWARDED_PROTO, "https"),
+            ]),
+        );
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test]
+    fn skip_same_origin_cors_require_tls() {
+        let config = Config::new().skip_same_origin_cors(true).require_tls(true);
+        let service = WebService::new(Echo, Cors::new(config));
+
+        let res = call(
+            &service,
+            grpc_web_request(&[
+                ("origin", "http://api.example.com"),
+                ("host", "api.example.com"),
+                (X_FORWARDED_PROTO, "http"),
+            ]),
+        );
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+
+        let res = call(
+            &service,
+            grpc_web_request(&[
+                ("origin", "https://api.example.com"),
+                ("host", "api.example.com"),
+                (X_FORWARDED_PROTO, "https"),
+            ]),
+        );
+        assert_eq!(res.status(), StatusCode::OK);
+        assert!(res
+            .headers()
+            .contains_key(header::STRICT_TRANSPORT_SECURITY));
+        assert!(!res
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+    }
+
+    #[derive(Clone)]
+    struct Slow;
+
+    impl Service<ServerContext, http::Request<hyper::Body>> for Slow {
+        type Response = http::Response<Body>;
+
+        type Error = Status;
+
+        type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+        fn call<'cx, 's>(
+            &'s self,
+            _: &'cx mut ServerContext,
+            _: http::Request<hyper::Body>,
+        ) -> Self::Future<'cx>
+        where
+            's: 'cx,
+        {
+            async move {
+                tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+                Ok(Response::new(Body::new(Box::pin(futures::stream::empty()))))
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn request_timeout() {
+        let config = Config::new().request_timeout(std::time::Duration::from_millis(10));
+        let service = WebService::new(Slow, Cors::new(config));
+        let req = http::Request::builder()
+            .method(Method::POST)
+            .header(CONTENT_TYPE, GRPC_WEB)
+            .body(hyper::Body::empty())
+            .unwrap();
+
+        let res = service
+            .call(&mut ServerContext::default(), req)
+            .await
+            .unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(res.into_body()).await.unwrap();
+        assert_eq!(body[0], 0x80);
+        assert!(body
+            .windows(b"grpc-status:4".len())
+            .any(|w| w == b"grpc-status:4"));
+    }
+
+    #[test]
+    fn text_json() {
+        let service = WebService::new(Echo, Cors::new(Config::new()));
+
+        let mut req = grpc_web_request(&[]);
+        req.headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static(GRPC_WEB_TEXT_JSON));
+        let res = call(&service, req);
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[CONTENT_TYPE], GRPC_WEB_TEXT_JSON);
+        // Echo copies the request it received, translated to gRPC, in its response.
+        assert_eq!(res.headers()[header::TE], "trailers");
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(GRPC_WEB_TEXT_JSON));
+        headers.insert(header::ACCEPT, HeaderValue::from_static(GRPC_WEB_TEXT_JSON));
+        assert!(matches!(
+            RequestKind::new(&headers, &Method::POST, Version::HTTP_11),
+            RequestKind::InFlight {
+                encoding: Encoding::Base64,
+                accept: Encoding::Base64,
+                content_type: GRPC_WEB_TEXT_JSON,
+                grpc_content_type: GRPC_JSON,
+                ..
+            }
+        ));
+
+        headers.insert(CONTENT_TYPE, HeaderValue::from_static(GRPC_WEB_TEXT_PROTO));
+        assert!(matches!(
+            RequestKind::new(&headers, &Method::POST, Version::HTTP_11),
+            RequestKind::InFlight {
+                grpc_content_type: GRPC,
+                ..
+            }
+        ));
+    }
+
+    fn html_request() -> http::Request<hyper::Body> {
+        http::Request::builder()
+            .uri("/index.html")
+            .header(header::ACCEPT, "text/html")
+            .body(hyper::Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn non_grpc_web_rejected_by_default() {
+        let service = WebService::new(Echo, Cors::new(Config::new()));
+
+        let res = call(&service, html_request());
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+        assert!(!res.headers().contains_key(header::ACCEPT));
+    }
+
+    #[test]
+    fn passthrough_non_grpc_web() {
+        let config = Config::new().passthrough_non_grpc_web(true);
+        let service = WebService::new(Echo, Cors::new(config));
+
+        let res = call(&service, html_request());
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[header::ACCEPT], "text/html");
+        assert!(!res.headers().contains_key(header::TE));
+        assert!(!res.headers().contains_key(CONTENT_TYPE));
+    }
+
+    #[test]
+    fn debug_latency_header() {
+        let service = WebService::new(Echo, Cors::new(Config::new()));
+        let res = call(&service, grpc_web_request(&[]));
+        assert!(!res.headers().contains_key("x-grpc-web-latency-ms"));
+
+        let config = Config::new().debug_latency_header(true);
+        let service = WebService::new(Echo, Cors::new(config));
+        let res = call(&service, grpc_web_request(&[]));
+        let latency = res.headers()["x-grpc-web-latency-ms"].to_str().unwrap();
+        assert!(latency.parse::<u64>().is_ok());
+
+        let config = Config::new()
+            .debug_latency_header(true)
+            .latency_header_name(header::HeaderName::from_static("x-latency"));
+        let service = WebService::new(Echo, Cors::new(config));
+        let res = call(&service, grpc_web_request(&[]));
+        assert!(res.headers().contains_key("x-latency"));
+        assert!(!res.headers().contains_key("x-grpc-web-latency-ms"));
+    }
+
+    #[test]
+    fn request_hook() {
+        let service = WebLayer::new(Cors::new(Config::new()))
+            .with_request_hook(|req| {
+                // The hook sees the request once translated to gRPC.
+                let content_type = req.headers()[CONTENT_TYPE].clone();
+                req.headers_mut().insert("x-hook-saw", content_type);
+                req.headers_mut()
+                    .insert(header::AUTHORIZATION, HeaderValue::from_static("Bearer t"));
+            })
+            .layer(Echo);
+
+        let res = call(&service, grpc_web_request(&[]));
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()["x-hook-saw"], GRPC);
+        assert_eq!(res.headers()[header::AUTHORIZATION], "Bearer t");
+    }
+
+    #[test]
+    fn skip_if() {
+        let service = WebLayer::new(Cors::new(Config::new()))
+            .skip_if(|req| req.uri().path() == "/healthz")
+            .layer(Echo);
+
+        let mut req = grpc_web_request(&[("origin", "https://example.com")]);
+        *req.uri_mut() = "/healthz".parse().unwrap();
+        let res = call(&service, req);
+        assert_eq!(res.headers()[CONTENT_TYPE], GRPC_WEB);
+        assert!(!res.headers().contains_key(header::TE));
+        assert!(!res
+            .headers()
+            .contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN));
+        assert!(!res.headers().contains_key(header::X_CONTENT_TYPE_OPTIONS));
+
+        let res = call(
+            &service,
+            grpc_web_request(&[("origin", "https://example.com")]),
+        );
+        assert_eq!(res.headers()[header::TE], "trailers");
+        assert_eq!(
+            res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://example.com"
+        );
+    }
+
+    #[test]
+    fn content_type_is_case_insensitive() {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            CONTENT_TYPE,
+            HeaderValue::from_static("APPLICATION/GRPC-WEB+PROTO"),
+        );
+
+        assert!(matches!(
+            RequestKind::new(&headers, &Method::POST, Version::HTTP_11),
+            RequestKind::InFlight {
+                encoding: Encoding::None,
+                ..
+            }
+        ));
+    }
+}
@@ -0,0 +1,265 @@
+//! Ready-made layers that compose with [`WebLayer`](crate::WebLayer).
+
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Instant,
+};
+
+use http::HeaderValue;
+use tracing::info;
+use volo::{Layer, Service};
+use volo_grpc::context::ServerContext;
+
+use crate::{Config, Cors, WebService};
+
+const X_REQUEST_ID: &str = "x-request-id";
+
+/// A [`Layer`] logging the method, path, status and latency of every request.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LoggingLayer;
+
+impl<S> Layer<S> for LoggingLayer {
+    type Service = Logging<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        Logging { inner }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Logging<S> {
+    inner: S,
+}
+
+impl<S, B> Service<ServerContext, http::Request<hyper::Body>> for Logging<S>
+where
+    S: Service<ServerContext, http::Request<hyper::Body>, Response = http::Response<B>>
+        + Send
+        + Sync
+        + 'static,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(
+        &'s self,
+        cx: &'cx mut ServerContext,
+        req: http::Request<hyper::Body>,
+    ) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let method = req.method().clone();
+            let path = req.uri().path().to_owned();
+            let start = Instant::now();
+
+            let res = self.inner.call(cx, req).await;
+            let elapsed = start.elapsed();
+
+            match &res {
+                Ok(res) => info!(%method, %path, status = %res.status(), ?elapsed),
+                Err(_) => info!(%method, %path, ?elapsed, "request failed"),
+            }
+
+            res
+        }
+    }
+}
+
+/// A [`Layer`] handling grpc-web requests from any origin, for local development only.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DevCorsLayer;
+
+impl<S> Layer<S> for DevCorsLayer {
+    type Service = WebService<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        WebService::new(inner, Cors::new(Config::default()))
+    }
+}
+
+/// A [`Layer`] giving every request an `x-request-id` header, unless it already has one, and
+/// echoing it in the response.
+#[derive(Clone, Debug, Default)]
+pub struct RequestIdLayer {
+    next_id: Arc<AtomicU64>,
+}
+
+impl RequestIdLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<S> Layer<S> for RequestIdLayer {
+    type Service = RequestId<S>;
+
+    fn layer(self, inner: S) -> Self::Service {
+        RequestId {
+            inner,
+            next_id: self.next_id,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RequestId<S> {
+    inner: S,
+    next_id: Arc<AtomicU64>,
+}
+
+impl<S, B> Service<ServerContext, http::Request<hyper::Body>> for RequestId<S>
+where
+    S: Service<ServerContext, http::Request<hyper::Body>, Response = http::Response<B>>
+        + Send
+        + Sync
+        + 'static,
+{
+    type Response = S::Response;
+
+    type Error = S::Error;
+
+    type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+    fn call<'cx, 's>(
+        &'s self,
+        cx: &'cx mut ServerContext,
+        mut req: http::Request<hyper::Body>,
+    ) -> Self::Future<'cx>
+    where
+        's: 'cx,
+    {
+        async move {
+            let id = match req.headers().get(X_REQUEST_ID) {
+                Some(id) => id.clone(),
+                None => {
+                    let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+                    let id = HeaderValue::from(id);
+                    req.headers_mut().insert(X_REQUEST_ID, id.clone());
+                    id
+                }
+            };
+
+            let mut res = self.inner.call(cx, req).await?;
+            res.headers_mut().entry(X_REQUEST_ID).or_insert(id);
+
+            Ok(res)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::executor::block_on;
+    use http::{header, StatusCode};
+    use volo_grpc::{body::Body, Status};
+
+    use super::*;
+
+    const X_SEEN_ID: &str = "x-seen-id";
+
+    /// Answers every request, echoing the request id it received.
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Service<ServerContext, http::Request<hyper::Body>> for Echo {
+        type Response = http::Response<Body>;
+
+        type Error = Status;
+
+        type Future<'cx> = impl Future<Output = Result<Self::Response, Self::Error>> + 'cx;
+
+        fn call<'cx, 's>(
+            &'s self,
+            _: &'cx mut ServerContext,
+            req: http::Request<hyper::Body>,
+        ) -> Self::Future<'cx>
+        where
+            's: 'cx,
+        {
+            async move {
+                let mut res = http::Response::new(Body::new(Box::pin(futures::stream::empty())));
+                if let Some(id) = req.headers().get(X_REQUEST_ID) {
+                    res.headers_mut().insert(X_SEEN_ID, id.clone());
+                }
+                Ok(res)
+            }
+        }
+    }
+
+    fn call<S>(service: &S, req: http::Request<hyper::Body>) -> http::Response<Body>
+    where
+        S: Service<ServerContext, http::Request<hyper::Body>, Response = http::Response<Body>>,
+        S::Error: std::fmt::Debug,
+    {
+        let mut cx = ServerContext::default();
+        block_on(service.call(&mut cx, req)).unwrap()
+    }
+
+    #[test]
+    fn request_id_keeps_incoming_id() {
+        let service = RequestIdLayer::new().layer(Echo);
+        let req = http::Request::builder()
+            .header(X_REQUEST_ID, "abc")
+            .body(hyper::Body::empty())
+            .unwrap();
+
+        let res = call(&service, req);
+
+        assert_eq!(res.headers()[X_SEEN_ID], "abc");
+        assert_eq!(res.headers()[X_REQUEST_ID], "abc");
+    }
+
+    #[test]
+    fn request_id_generates_ids() {
+        let service = RequestIdLayer::new().layer(Echo);
+
+        for expected in ["0", "1"] {
+            let res = call(&service, http::Request::new(hyper::Body::empty()));
+
+            assert_eq!(res.headers()[X_SEEN_ID], expected);
+            assert_eq!(res.headers()[X_REQUEST_ID], expected);
+        }
+    }
+
+    #[test]
+    fn dev_cors_allows_any_origin() {
+        let service = DevCorsLayer.layer(Echo);
+
+        for origin in ["https://example.com", "http://localhost:3000"] {
+            let req = http::Request::builder()
+                .method(http::Method::POST)
+                .header(header::CONTENT_TYPE, "application/grpc-web")
+                .header(header::ORIGIN, origin)
+                .body(hyper::Body::empty())
+                .unwrap();
+
+            let res = call(&service, req);
+
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(res.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN], origin);
+        }
+    }
+
+    #[test]
+    fn logging_passes_responses_through() {
+        let service = LoggingLayer.layer(Echo);
+        let req = http::Request::builder()
+            .header(X_REQUEST_ID, "abc")
+            .body(hyper::Body::empty())
+            .unwrap();
+
+        let res = call(&service, req);
+
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers()[X_SEEN_ID], "abc");
+    }
+}
@@ -0,0 +1,218 @@
+//! The framing of grpc-web bodies, as described in the [protocol spec][spec].
+//!
+//! Every message is sent as a data frame: a flags byte, a big-endian `u32` length and the message
+//! itself. The trailers follow in a last frame with the [`TRAILERS_FLAG`] set, holding them as
+//! `name:value\r\n` lines.
+//!
+//! [spec]: https://github.com/grpc/grpc/blob/master/doc/PROTOCOL-WEB.md
+
+use std::fmt;
+
+use bytes::{BufMut, Bytes, BytesMut};
+use http::{header::HeaderName, HeaderMap, HeaderValue};
+
+/// The length of the flags byte and the message length preceding every frame.
+pub const FRAME_HEADER_SIZE: usize = 5;
+
+/// The flag marking a frame as holding the trailers.
+pub const TRAILERS_FLAG: u8 = 0b1000_0000;
+
+/// The flag marking the message of a frame as compressed.
+pub const COMPRESSED_FLAG: u8 = 0b0000_0001;
+
+/// The header preceding every frame.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FrameHeader {
+    pub compression: bool,
+    pub length: u32,
+}
+
+/// A single frame of a grpc-web body.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DataFrame {
+    pub flags: u8,
+    pub body: Bytes,
+}
+
+impl DataFrame {
+    pub fn is_trailers(&self) -> bool {
+        self.flags & TRAILERS_FLAG != 0
+    }
+}
+
+/// The trailers sent in the last frame of a grpc-web response.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrailerFrame {
+    pub headers: HeaderMap,
+}
+
+/// A trailers frame that could not be decoded.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum TrailerFrameError {
+    /// The frame does not have the [`TRAILERS_FLAG`] set.
+    NotTrailers,
+    /// A line is not a valid `name:value` header.
+    Malformed,
+}
+
+impl fmt::Display for TrailerFrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TrailerFrameError::NotTrailers => f.write_str("not a trailers frame"),
+            TrailerFrameError::Malformed => f.write_str("malformed trailers frame"),
+        }
+    }
+}
+
+impl std::error::Error for TrailerFrameError {}
+
+/// Encodes `frame`, header included.
+///
+/// # Panics
+///
+/// Panics if the body is longer than `u32::MAX` bytes.
+pub fn encode_data_frame(frame: &DataFrame) -> Bytes {
+    let len = u32::try_from(frame.body.len()).expect("grpc-web frame too large");
+
+    let mut buf = BytesMut::with_capacity(FRAME_HEADER_SIZE + frame.body.len());
+    buf.put_u8(frame.flags);
+    buf.put_u32(len);
+    buf.put_slice(&frame.body);
+
+    buf.freeze()
+}
+
+/// Splits the next frame off `buf`, or returns `None` if `buf` does not hold a whole frame yet.
+pub fn decode_data_frame(buf: &mut BytesMut) -> Option<DataFrame> {
+    if buf.len() < FRAME_HEADER_SIZE {
+        return None;
+    }
+
+    let len = frame_len(buf);
+    if buf.len() < FRAME_HEADER_SIZE + len {
+        return None;
+    }
+
+    let mut frame = buf.split_to(FRAME_HEADER_SIZE + len);
+    let flags = frame[0];
+    let body = frame.split_off(FRAME_HEADER_SIZE).freeze();
+
+    Some(DataFrame { flags, body })
+}
+
+/// Encodes `frame` as a data frame with the [`TRAILERS_FLAG`] set.
+pub fn encode_trailer_frame(frame: &TrailerFrame) -> Bytes {
+    let body = frame
+        .headers
+        .iter()
+        .fold(BytesMut::new(), |mut acc, (key, value)| {
+            acc.put_slice(key.as_ref());
+            acc.put_u8(b':');
+            acc.put_slice(value.as_bytes());
+            acc.put_slice(b"\r\n");
+            acc
+        });
+
+    encode_data_frame(&DataFrame {
+        flags: TRAILERS_FLAG,
+        body: body.freeze(),
+    })
+}
+
+/// Splits the next frame off `buf` and decodes it as trailers, or returns `Ok(None)` if `buf`
+/// does not hold a whole frame yet.
+pub fn decode_trailer_frame(buf: &mut BytesMut) -> Result<Option<TrailerFrame>, TrailerFrameError> {
+    let frame = match decode_data_frame(buf) {
+        Some(frame) => frame,
+        None => return Ok(None),
+    };
+
+    if !frame.is_trailers() {
+        return Err(TrailerFrameError::NotTrailers);
+    }
+
+    let mut headers = HeaderMap::new();
+
+    for line in frame.body.split(|&b| b == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if line.is_empty() {
+            continue;
+        }
+
+        let colon = line
+            .iter()
+            .position(|&b| b == b':')
+            .ok_or(TrailerFrameError::Malformed)?;
+        let name =
+            HeaderName::from_bytes(&line[..colon]).map_err(|_| TrailerFrameError::Malformed)?;
+        let value = &line[colon + 1..];
+        let start = value
+            .iter()
+            .position(|b| !b.is_ascii_whitespace())
+            .unwrap_or(value.len());
+        let value =
+            HeaderValue::from_bytes(&value[start..]).map_err(|_| TrailerFrameError::Malformed)?;
+
+        headers.append(name, value);
+    }
+
+    Ok(Some(TrailerFrame { headers }))
+}
+
+/// Reads the message length from a frame header.
+pub(crate) fn frame_len(header: &[u8]) -> usize {
+    u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_frame_round_trip() {
+        let frame = DataFrame {
+            flags: 0,
+            body: Bytes::from_static(b"hello"),
+        };
+
+        let mut buf = BytesMut::from(&encode_data_frame(&frame)[..]);
+        assert_eq!(&buf[..], b"\x00\x00\x00\x00\x05hello");
+        assert_eq!(decode_data_frame(&mut buf), Some(frame));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn incomplete_data_frame() {
+        let mut buf = BytesMut::from(&b"\x00\x00\x00\x00\x05hel"[..]);
+        assert_eq!(decode_data_frame(&mut buf), None);
+        assert_eq!(buf.len(), 8);
+    }
+
+    #[test]
+    fn trailer_frame_round_trip() {
+        let mut headers = HeaderMap::new();
+        headers.insert("grpc-status", HeaderValue::from_static("0"));
+        headers.insert("grpc-message", HeaderValue::from_static("ok"));
+        let frame = TrailerFrame { headers };
+
+        let mut buf = BytesMut::from(&encode_trailer_frame(&frame)[..]);
+        assert_eq!(buf[0], TRAILERS_FLAG);
+        assert_eq!(decode_trailer_frame(&mut buf).unwrap(), Some(frame));
+    }
+
+    #[test]
+    fn decode_trailer_frame_errors() {
+        let mut buf = BytesMut::from(&b"\x00\x00\x00\x00\x02ab"[..]);
+        assert!(matches!(
+            decode_trailer_frame(&mut buf),
+            Err(TrailerFrameError::NotTrailers)
+        ));
+
+        let mut buf = BytesMut::from(&b"\x80\x00\x00\x00\x04ab\r\n"[..]);
+        assert!(matches!(
+            decode_trailer_frame(&mut buf),
+            Err(TrailerFrameError::Malformed)
+        ));
+    }
+}
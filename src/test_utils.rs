@@ -0,0 +1,116 @@
+//! Helpers to run servers in integration tests.
+
+use std::{
+    future::Future,
+    net::{Ipv6Addr, SocketAddr, TcpListener},
+    time::{Duration, Instant},
+};
+
+use tokio::{net::TcpStream, task::JoinHandle};
+use volo::net::Address;
+use volo_grpc::BoxError;
+
+const DEFAULT_STARTUP_TIMEOUT: Duration = Duration::from_secs(5);
+const STARTUP_POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+/// A server spawned on the tokio runtime for the duration of a test.
+pub struct TestServer;
+
+impl TestServer {
+    pub fn builder() -> TestServerBuilder {
+        TestServerBuilder {
+            port: 0,
+            startup_timeout: DEFAULT_STARTUP_TIMEOUT,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TestServerBuilder {
+    port: u16,
+    startup_timeout: Duration,
+}
+
+impl TestServerBuilder {
+    /// Sets the port to listen on. The default, `0`, picks any free port, see
+    /// [`TestServerHandle::address`].
+    #[must_use]
+    pub fn port(self, port: u16) -> Self {
+        Self { port, ..self }
+    }
+
+    /// Sets how long [`TestServerBuilder::spawn`] waits at most for the server to accept
+    /// connections.
+    #[must_use]
+    pub fn startup_timeout(self, startup_timeout: Duration) -> Self {
+        Self {
+            startup_timeout,
+            ..self
+        }
+    }
+
+    /// Spawns the server returned by `serve`, usually a volo-grpc `Server` with or without a
+    /// [`WebLayer`](crate::WebLayer), run on the given address, and waits until it accepts
+    /// connections.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the server stops, for instance because its port was taken, or does not accept
+    /// connections within the startup timeout.
+    pub async fn spawn<F, Fut>(self, serve: F) -> TestServerHandle
+    where
+        F: FnOnce(Address) -> Fut,
+        Fut: Future<Output = Result<(), BoxError>> + Send + 'static,
+    {
+        let addr: SocketAddr = format!("[::]:{}", self.port).parse().unwrap();
+        // Resolve port 0 to a free port, released right before the server binds it. Should another
+        // socket take it in between, the server fails to bind and the wait below reports it.
+        let addr = TcpListener::bind(addr).unwrap().local_addr().unwrap();
+        let server = serve(Address::from(addr));
+
+        let task = tokio::spawn(async move { server.await.unwrap() });
+
+        let mut probe = addr;
+        if probe.ip().is_unspecified() {
+            probe.set_ip(Ipv6Addr::LOCALHOST.into());
+        }
+        let deadline = Instant::now() + self.startup_timeout;
+        while TcpStream::connect(probe).await.is_err() {
+            assert!(
+                !task.is_finished(),
+                "test server on {addr} stopped before accepting connections"
+            );
+            assert!(
+                Instant::now() < deadline,
+                "test server on {addr} did not accept connections within {:?}",
+                self.startup_timeout
+            );
+            tokio::time::sleep(STARTUP_POLL_INTERVAL).await;
+        }
+
+        TestServerHandle { addr, task }
+    }
+}
+
+#[derive(Debug)]
+pub struct TestServerHandle {
+    addr: SocketAddr,
+    task: JoinHandle<()>,
+}
+
+impl TestServerHandle {
+    /// Returns the address the server listens on, with the port it was actually given.
+    pub fn address(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Returns the `http://` base url of the server.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Stops the server.
+    pub fn shutdown(self) {
+        self.task.abort();
+    }
+}